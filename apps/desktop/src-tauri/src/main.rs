@@ -1,10 +1,14 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use tauri::menu::{Menu, MenuItem, PredefinedMenuItem, Submenu};
 use tauri::Emitter;
 use timelens_core::analysis::labeling::LabelConfig;
-use timelens_core::pgn::parse_time_control_value;
+use timelens_core::analysis::pipeline::EvalSource;
+use timelens_core::engine::EngineTimeouts;
+use timelens_core::pgn::{parse_delay_value, parse_time_control_value};
 use timelens_core::AnalysisConfig;
 
 #[derive(Debug, Deserialize, Clone, Default)]
@@ -12,44 +16,113 @@ struct AnalysisOptions {
     depth: Option<u16>,
     multipv: Option<u8>,
     movetime_ms: Option<u64>,
+    nodes: Option<u64>,
     threads: Option<u32>,
     hash_mb: Option<u32>,
+    engine_options: Option<BTreeMap<String, String>>,
+    engine_handshake_timeout_ms: Option<u64>,
+    engine_timeout_ms: Option<u64>,
+    engine_restarts: Option<u32>,
+    engine_pool_size: Option<usize>,
+    eval_cache_dir: Option<String>,
+    refresh_eval_cache: Option<bool>,
+    eval_source: Option<String>,
+    analyze_variations: Option<bool>,
     time_control: Option<String>,
+    delay: Option<String>,
+    white_clock_secs: Option<u32>,
+    black_clock_secs: Option<u32>,
+    armageddon: Option<bool>,
+    exclude_suspect_clocks: Option<bool>,
+    interpolate_clocks: Option<bool>,
     alpha: Option<f32>,
     beta: Option<f32>,
     time_pressure_pivot: Option<f32>,
     time_pressure_scale: Option<f32>,
     time_pressure_boost: Option<f32>,
     k_sigmoid: Option<f32>,
+    use_wdl: Option<bool>,
 }
 
 impl AnalysisOptions {
     fn to_config(&self, engine_path: String) -> Result<AnalysisConfig, String> {
         let base = AnalysisConfig::default();
-        
+
         let fallback_time_control = if let Some(tc) = &self.time_control {
-            let parsed = parse_time_control_value(tc)
-                .ok_or_else(|| format!("Invalid TimeControl '{}'. Use format like 180+2.", tc))?;
+            let parsed = parse_time_control_value(tc).ok_or_else(|| {
+                format!(
+                    "Invalid TimeControl '{}'. Use format like 180+2 or 40/5400+30:1800+30.",
+                    tc
+                )
+            })?;
             Some(parsed)
         } else {
             None
         };
 
+        let delay = match &self.delay {
+            Some(spec) => Some(parse_delay_value(spec).ok_or_else(|| {
+                format!(
+                    "Invalid delay '{}'. Use d5 (US delay) or b5 (Bronstein).",
+                    spec
+                )
+            })?),
+            None => None,
+        };
+
+        let eval_source = match &self.eval_source {
+            Some(name) => EvalSource::from_name(name).ok_or_else(|| {
+                format!(
+                    "Invalid eval source '{}'. Use engine, embedded, or mixed.",
+                    name
+                )
+            })?,
+            None => base.eval_source,
+        };
+
         Ok(AnalysisConfig {
             engine_path,
             multipv: self.multipv.unwrap_or(base.multipv),
             depth: self.depth.unwrap_or(base.depth),
             movetime_ms: self.movetime_ms.or(base.movetime_ms),
+            nodes: self.nodes.or(base.nodes),
             threads: self.threads.or(base.threads),
             hash_mb: self.hash_mb.or(base.hash_mb),
+            engine_options: self.engine_options.clone().unwrap_or(base.engine_options),
+            engine_timeouts: EngineTimeouts {
+                handshake_ms: self
+                    .engine_handshake_timeout_ms
+                    .unwrap_or(base.engine_timeouts.handshake_ms),
+                read_ms: self
+                    .engine_timeout_ms
+                    .unwrap_or(base.engine_timeouts.read_ms),
+            },
+            engine_restarts: self.engine_restarts.unwrap_or(base.engine_restarts),
+            engine_pool_size: self.engine_pool_size.unwrap_or(base.engine_pool_size),
+            eval_cache_dir: self.eval_cache_dir.as_ref().map(PathBuf::from),
+            eval_cache_max_entries: base.eval_cache_max_entries,
+            refresh_eval_cache: self.refresh_eval_cache.unwrap_or(base.refresh_eval_cache),
+            eval_source,
+            analyze_variations: self.analyze_variations.unwrap_or(base.analyze_variations),
             fallback_time_control,
+            delay,
+            white_clock_secs: self.white_clock_secs.or(base.white_clock_secs),
+            black_clock_secs: self.black_clock_secs.or(base.black_clock_secs),
+            armageddon: self.armageddon.unwrap_or(base.armageddon),
+            interpolate_clocks: self.interpolate_clocks.unwrap_or(base.interpolate_clocks),
             alpha: self.alpha.unwrap_or(base.alpha),
             beta: self.beta.unwrap_or(base.beta),
             time_pressure_pivot: self.time_pressure_pivot.unwrap_or(base.time_pressure_pivot),
             time_pressure_scale: self.time_pressure_scale.unwrap_or(base.time_pressure_scale),
             time_pressure_boost: self.time_pressure_boost.unwrap_or(base.time_pressure_boost),
             k_sigmoid: self.k_sigmoid.unwrap_or(base.k_sigmoid),
-            label_config: base.label_config,
+            use_wdl: self.use_wdl.unwrap_or(base.use_wdl),
+            label_config: LabelConfig {
+                exclude_suspect_clocks: self
+                    .exclude_suspect_clocks
+                    .unwrap_or(base.label_config.exclude_suspect_clocks),
+                ..base.label_config
+            },
         })
     }
 }
@@ -60,16 +133,15 @@ async fn analyze_pgn_text(
     engine_path: String,
     options: Option<AnalysisOptions>,
 ) -> Result<String, String> {
-    if engine_path.trim().is_empty() {
-        return Err("Engine path is required.".to_string());
-    }
-
     let options = options.unwrap_or_default();
     let cfg = options.to_config(engine_path)?;
+    if cfg.eval_source != EvalSource::Embedded && cfg.engine_path.trim().is_empty() {
+        return Err("Engine path is required.".to_string());
+    }
 
     let analysis = timelens_core::analysis::pipeline::analyze_pgn(&pgn, cfg)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| format!("{:#}", e))?;
 
     serde_json::to_string(&analysis).map_err(|e| e.to_string())
 }
@@ -89,12 +161,7 @@ fn build_menu(app: &tauri::AppHandle) -> tauri::Result<Menu<tauri::Wry>> {
     let preferences = MenuItem::with_id(app, "preferences", "Preferences", true, None::<&str>)?;
     let quit = PredefinedMenuItem::quit(app, None)?;
 
-    let submenu = Submenu::with_items(
-        app,
-        "TempoLens",
-        true,
-        &[&about, &preferences, &quit],
-    )?;
+    let submenu = Submenu::with_items(app, "TempoLens", true, &[&about, &preferences, &quit])?;
 
     Menu::with_items(app, &[&submenu])
 }
@@ -41,6 +41,23 @@ export function MoveDetailPanel({ ply }: Props) {
             <span className="sub-detail">Rem: {(ply.ply.clock_after_secs ?? 0).toFixed(0)}s</span>
           </div>
         </div>
+        {ply.alternatives && ply.alternatives.length > 0 && (
+          <div className="alternatives">
+            {ply.alternatives.map((alt, idx) => (
+              <div key={idx}>
+                <strong>{alt.line_san.join(" ")}</strong>
+                {alt.gain_cp_mover != null && (
+                  <span className="sub-detail">
+                    {" "}
+                    {alt.gain_cp_mover > 0 ? "+" : ""}
+                    {(alt.gain_cp_mover / 100).toFixed(2)} vs played after{" "}
+                    {(ply.ply.think_time_secs ?? 0).toFixed(1)}s
+                  </span>
+                )}
+              </div>
+            ))}
+          </div>
+        )}
         <div className="tips">
           {ply.label.tips.map((tip, idx) => (
             <div key={idx}>• {tip}</div>
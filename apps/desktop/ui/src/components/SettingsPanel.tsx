@@ -48,6 +48,15 @@ export function SettingsPanel({ options, onChange, onReset }: Props) {
             }
           />
         </label>
+        <label className="field field-small">
+          <span>Nodes</span>
+          <input
+            type="number"
+            min={1}
+            value={options.nodes ?? ""}
+            onChange={(e) => update({ nodes: e.target.value ? parseInt(e.target.value, 10) : null })}
+          />
+        </label>
       </div>
 
       <div className="field-row">
@@ -55,7 +55,21 @@ export function AnalyzePage() {
   const timeControlLabel = useMemo(() => {
     if (!analysis?.meta?.time_control) return null;
     const tc = analysis.meta.time_control;
-    return `${tc.base_secs / 60}+${tc.increment_secs}`;
+    const stages = [tc, ...(tc.later_stages ?? [])];
+    let label = stages
+      .map((s) => `${s.moves ? `${s.moves}/` : ""}${s.base_secs / 60}+${s.increment_secs}`)
+      .join(" : ");
+    if (tc.black_base_secs != null) {
+      label = `${label} (Black ${tc.black_base_secs / 60})`;
+    }
+    if (analysis.meta.armageddon) {
+      label = `${label} · Armageddon`;
+    }
+    if (analysis.meta.time_control_source === "Inferred") {
+      label = `${label} (inferred)`;
+    }
+    if (!tc.delay) return label;
+    return `${label} · ${tc.delay.kind === "Bronstein" ? "Bronstein" : "delay"} ${tc.delay.secs}s`;
   }, [analysis]);
 
   const onAnalyze = async () => {
@@ -2,9 +2,24 @@ export type Color = "White" | "Black";
 
 export type SourcePlatform = "Lichess" | "ChessCom" | "Unknown";
 
+export interface TimeControlStage {
+  moves?: number | null;
+  base_secs: number;
+  increment_secs: number;
+}
+
+export interface TimeDelay {
+  kind: "Simple" | "Bronstein";
+  secs: number;
+}
+
 export interface TimeControl {
   base_secs: number;
   increment_secs: number;
+  moves?: number | null;
+  later_stages?: TimeControlStage[];
+  delay?: TimeDelay | null;
+  black_base_secs?: number | null;
 }
 
 export interface PlyRecord {
@@ -17,6 +32,18 @@ export interface PlyRecord {
   clock_after_secs?: number | null;
   clock_before_secs?: number | null;
   think_time_secs?: number | null;
+  think_time_estimated?: boolean;
+  premove?: boolean;
+  premove_estimated?: boolean;
+  elapsed_move_secs?: number | null;
+  comment?: string | null;
+  variations?: PlyRecord[][];
+}
+
+export interface Wdl {
+  win: number;
+  draw: number;
+  loss: number;
 }
 
 export interface EngineLine {
@@ -24,8 +51,14 @@ export interface EngineLine {
   uci: string;
   cp_white: number;
   mate?: number | null;
+  wdl?: Wdl | null;
+  seldepth?: number | null;
+  pv_uci?: string[];
+  pv_san?: string[];
 }
 
+export type Score = { Cp: number } | { Mate: number };
+
 export interface EngineSummary {
   depth: number;
   nodes: number;
@@ -33,6 +66,8 @@ export interface EngineSummary {
   lines: EngineLine[];
   played_cp_white?: number | null;
   best_cp_white?: number | null;
+  played_score_white?: Score | null;
+  best_score_white?: Score | null;
   punish_cp_mover?: number | null;
   spread_k_cp_mover?: number | null;
   gap_12_cp_mover?: number | null;
@@ -61,11 +96,34 @@ export interface MoveMetrics {
   dp_practical_mover: number;
 }
 
+export interface AlternativeMove {
+  san: string;
+  uci: string;
+  line_san: string[];
+  comment?: string | null;
+  cp_white?: number | null;
+  gain_cp_mover?: number | null;
+}
+
 export interface PlyAnalysis {
   ply: PlyRecord;
   engine_before: EngineSummary;
   metrics: MoveMetrics;
   label: Label;
+  alternatives?: AlternativeMove[];
+}
+
+export interface EngineInfo {
+  name?: string | null;
+  author?: string | null;
+  options: Record<string, string>;
+}
+
+export interface SearchLimits {
+  depth?: number | null;
+  movetime_ms?: number | null;
+  nodes?: number | null;
+  multipv: number;
 }
 
 export interface GameMeta {
@@ -77,8 +135,16 @@ export interface GameMeta {
   black?: string;
   result?: string;
   time_control?: TimeControl | null;
+  time_control_source?: "Header" | "Fallback" | "Inferred" | null;
+  time_control_confidence?: number | null;
+  armageddon?: boolean;
+  search_limits?: SearchLimits | null;
+  engine?: EngineInfo | null;
+  config_hash?: string;
+  engine_restarts?: number;
   platform: SourcePlatform;
   headers?: Record<string, string>;
+  warnings?: string[];
 }
 
 export interface GameSummary {
@@ -117,25 +183,58 @@ export interface GameSummary {
   };
 }
 
+export type ClockAnomalyKind = "Missing" | "UnexpectedGain" | "Overspent";
+
+export interface ClockAnomaly {
+  ply_index: number;
+  mover: Color;
+  kind: ClockAnomalyKind;
+  magnitude_secs: number;
+}
+
+export interface ClockDiagnostics {
+  anomalies: ClockAnomaly[];
+  confidence: number;
+  warnings?: string[];
+}
+
 export interface GameAnalysis {
   meta: GameMeta;
   plies: PlyAnalysis[];
   summary: GameSummary;
+  clock_diagnostics?: ClockDiagnostics;
 }
 
 export interface AnalysisOptions {
   depth: number;
   multipv: number;
   movetime_ms?: number | null;
+  nodes?: number | null;
   threads?: number | null;
   hash_mb?: number | null;
+  engine_options?: Record<string, string> | null;
+  engine_handshake_timeout_ms?: number | null;
+  engine_timeout_ms?: number | null;
+  engine_restarts?: number | null;
+  engine_pool_size?: number | null;
+  eval_cache_dir?: string | null;
+  refresh_eval_cache?: boolean | null;
+  eval_source?: "engine" | "embedded" | "mixed" | null;
+  analyze_variations?: boolean | null;
   time_control?: string | null;
+  delay?: string | null;
+  white_clock_secs?: number | null;
+  black_clock_secs?: number | null;
+  armageddon?: boolean | null;
+  exclude_suspect_clocks?: boolean | null;
+  interpolate_clocks?: boolean | null;
   alpha: number;
   beta: number;
   time_pressure_pivot: number;
   time_pressure_scale: number;
   time_pressure_boost: number;
   k_sigmoid: number;
+  use_wdl?: boolean | null;
 }
 
 export interface RecentPgnEntry {
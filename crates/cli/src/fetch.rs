@@ -10,14 +10,17 @@ pub enum FetchSource {
     ChessCom,
 }
 
+/// `with_evals` asks Lichess for its `[%eval]` annotations; Chess.com exports have none.
 pub async fn fetch_pgn(
     source: FetchSource,
     user: &str,
     games: usize,
     cache_dir: &Path,
     refresh: bool,
+    with_evals: bool,
 ) -> Result<String> {
-    let cache_path = cache_path(cache_dir, source, user, games);
+    let with_evals = with_evals && matches!(source, FetchSource::Lichess);
+    let cache_path = cache_path(cache_dir, source, user, games, with_evals);
     if !refresh {
         if let Ok(text) = fs::read_to_string(&cache_path) {
             if !text.trim().is_empty() {
@@ -27,7 +30,7 @@ pub async fn fetch_pgn(
     }
 
     let text = match source {
-        FetchSource::Lichess => fetch_lichess_pgn(user, games).await?,
+        FetchSource::Lichess => fetch_lichess_pgn(user, games, with_evals).await?,
         FetchSource::ChessCom => fetch_chesscom_pgn(user, games).await?,
     };
 
@@ -59,22 +62,31 @@ pub fn resolve_cache_dir(override_dir: Option<&PathBuf>) -> Result<PathBuf> {
     Ok(PathBuf::from(".timelens/cache"))
 }
 
-fn cache_path(base: &Path, source: FetchSource, user: &str, games: usize) -> PathBuf {
+fn cache_path(
+    base: &Path,
+    source: FetchSource,
+    user: &str,
+    games: usize,
+    with_evals: bool,
+) -> PathBuf {
     let platform = match source {
         FetchSource::Lichess => "lichess",
         FetchSource::ChessCom => "chesscom",
     };
-    base.join(platform)
-        .join(user)
-        .join(format!("last_{}.pgn", games))
+    let file = if with_evals {
+        format!("last_{}_evals.pgn", games)
+    } else {
+        format!("last_{}.pgn", games)
+    };
+    base.join(platform).join(user).join(file)
 }
 
-async fn fetch_lichess_pgn(user: &str, games: usize) -> Result<String> {
+async fn fetch_lichess_pgn(user: &str, games: usize, with_evals: bool) -> Result<String> {
     let client = client();
     let encoded = urlencoding::encode(user);
     let url = format!(
-        "https://lichess.org/api/games/user/{}?max={}&clocks=true&evals=false&opening=false",
-        encoded, games
+        "https://lichess.org/api/games/user/{}?max={}&clocks=true&evals={}&opening=false",
+        encoded, games, with_evals
     );
 
     let response = client
@@ -1,16 +1,33 @@
 mod fetch;
 
 use anyhow::{anyhow, Result};
-use clap::Parser;
-use std::path::PathBuf;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use timelens_core::analysis::cache::DEFAULT_MAX_ENTRIES;
 use timelens_core::analysis::labeling::LabelConfig;
-use timelens_core::pgn::parse_time_control_value;
+use timelens_core::analysis::pipeline::{analyze_game_stream, EvalSource};
+use timelens_core::analysis::profile::load_profile;
+use timelens_core::engine::EngineTimeouts;
+use timelens_core::model::GameOutcome;
+use timelens_core::pgn::{
+    open_pgn_file, open_pgn_source, parse_delay_value, parse_time_control_value, read_games,
+};
 use timelens_core::AnalysisConfig;
 
 #[derive(Parser)]
 struct Args {
     #[arg(long)]
-    engine: String,
+    engine: Option<String>,
+    #[arg(long)]
+    profile: Option<String>,
+    #[arg(long)]
+    profiles: Option<PathBuf>,
+    #[arg(long = "uci-option", value_name = "NAME=VALUE")]
+    uci_options: Vec<String>,
     #[arg(long)]
     pgn: Option<String>,
     #[arg(long)]
@@ -23,18 +40,40 @@ struct Args {
     cache_dir: Option<PathBuf>,
     #[arg(long, default_value_t = false)]
     refresh_cache: bool,
+    #[arg(long, default_value_t = false)]
+    no_eval_cache: bool,
+    #[arg(long, default_value_t = false)]
+    refresh_eval_cache: bool,
+    #[arg(long, default_value_t = DEFAULT_MAX_ENTRIES)]
+    eval_cache_max_entries: usize,
     #[arg(long)]
     output: Option<String>,
+    #[arg(long, default_value_t = false)]
+    stream: bool,
     #[arg(long, default_value_t = 14)]
     depth: u16,
     #[arg(long, default_value_t = 4)]
     multipv: u8,
     #[arg(long)]
     movetime_ms: Option<u64>,
+    #[arg(long, conflicts_with = "movetime_ms")]
+    nodes: Option<u64>,
     #[arg(long)]
     threads: Option<u32>,
     #[arg(long)]
     hash_mb: Option<u32>,
+    #[arg(long, default_value_t = 1)]
+    engine_pool: usize,
+    #[arg(long, default_value_t = 2_000)]
+    engine_handshake_timeout_ms: u64,
+    #[arg(long, default_value_t = 10_000)]
+    engine_timeout_ms: u64,
+    #[arg(long, default_value_t = 2)]
+    engine_restarts: u32,
+    #[arg(long, default_value = "engine")]
+    eval_source: String,
+    #[arg(long, default_value_t = false)]
+    analyze_variations: bool,
     #[arg(long, default_value_t = 2.0)]
     alpha: f32,
     #[arg(long, default_value_t = 10.0)]
@@ -47,13 +86,28 @@ struct Args {
     time_pressure_boost: f32,
     #[arg(long, default_value_t = 1.2)]
     k_sigmoid: f32,
+    #[arg(long, default_value_t = false)]
+    no_wdl: bool,
     #[arg(long)]
     time_control: Option<String>,
+    #[arg(long)]
+    delay: Option<String>,
+    #[arg(long)]
+    white_clock: Option<u32>,
+    #[arg(long)]
+    black_clock: Option<u32>,
+    #[arg(long, default_value_t = false)]
+    armageddon: bool,
+    #[arg(long, default_value_t = false)]
+    exclude_suspect_clocks: bool,
+    #[arg(long, default_value_t = false)]
+    interpolate_clocks: bool,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
     let source_count = args.pgn.is_some() as u8
         + args.lichess_user.is_some() as u8
@@ -72,7 +126,7 @@ async fn main() -> Result<()> {
             Some(parsed) => Some(parsed),
             None => {
                 return Err(anyhow!(
-                    "Invalid --time-control value '{}'. Use format like 180+2.",
+                    "Invalid --time-control value '{}'. Use a format like 180+2, 40/5400+30:1800+30 or G/90;d5.",
                     tc
                 ));
             }
@@ -81,29 +135,103 @@ async fn main() -> Result<()> {
         None
     };
 
-    let cfg = AnalysisConfig {
-        engine_path: args.engine,
+    let delay = match args.delay.as_deref() {
+        Some(spec) => Some(parse_delay_value(spec).ok_or_else(|| {
+            anyhow!(
+                "Invalid --delay value '{}'. Use d5 (US delay) or b5 (Bronstein).",
+                spec
+            )
+        })?),
+        None => None,
+    };
+
+    let eval_source = EvalSource::from_name(&args.eval_source).ok_or_else(|| {
+        anyhow!(
+            "Invalid --eval-source value '{}'. Use engine, embedded, or mixed.",
+            args.eval_source
+        )
+    })?;
+
+    let eval_cache_dir = if args.no_eval_cache {
+        None
+    } else {
+        Some(fetch::resolve_cache_dir(args.cache_dir.as_ref())?.join("evals"))
+    };
+
+    let mut cfg = AnalysisConfig {
+        engine_path: args.engine.clone().unwrap_or_default(),
         multipv: args.multipv,
         depth: args.depth,
         movetime_ms: args.movetime_ms,
+        nodes: args.nodes,
         threads: args.threads,
         hash_mb: args.hash_mb,
+        engine_options: BTreeMap::new(),
+        engine_timeouts: EngineTimeouts {
+            handshake_ms: args.engine_handshake_timeout_ms,
+            read_ms: args.engine_timeout_ms,
+        },
+        engine_restarts: args.engine_restarts,
+        engine_pool_size: args.engine_pool,
+        eval_cache_dir,
+        eval_cache_max_entries: args.eval_cache_max_entries,
+        refresh_eval_cache: args.refresh_eval_cache,
+        eval_source,
+        analyze_variations: args.analyze_variations,
         fallback_time_control,
+        delay,
+        white_clock_secs: args.white_clock,
+        black_clock_secs: args.black_clock,
+        armageddon: args.armageddon,
+        interpolate_clocks: args.interpolate_clocks,
         alpha: args.alpha,
         beta: args.beta,
         time_pressure_pivot: args.time_pressure_pivot,
         time_pressure_scale: args.time_pressure_scale,
         time_pressure_boost: args.time_pressure_boost,
         k_sigmoid: args.k_sigmoid,
-        label_config: LabelConfig::default(),
+        use_wdl: !args.no_wdl,
+        label_config: LabelConfig {
+            exclude_suspect_clocks: args.exclude_suspect_clocks,
+            ..LabelConfig::default()
+        },
     };
 
+    if let Some(name) = args.profile.as_deref() {
+        let path = resolve_profiles_path(args.profiles.as_ref());
+        load_profile(&path, name)?.apply(&mut cfg);
+        apply_explicit_engine_flags(&mut cfg, &args, &matches);
+    }
+    for spec in args.uci_options.iter() {
+        let (name, value) = spec.split_once('=').ok_or_else(|| {
+            anyhow!(
+                "Invalid --uci-option value '{}'. Use NAME=VALUE, e.g. SyzygyPath=/tb.",
+                spec
+            )
+        })?;
+        cfg.engine_options
+            .insert(name.trim().to_string(), value.trim().to_string());
+    }
+    if cfg.engine_path.is_empty() && eval_source != EvalSource::Embedded {
+        return Err(anyhow!(
+            "--engine (or a --profile with a path) is required unless --eval-source embedded is used."
+        ));
+    }
+
+    if args.stream {
+        return stream_analyses(&args, cfg).await;
+    }
+
     let pgn_text = load_pgn_text(&args).await?;
-    let analyses = timelens_core::analysis::pipeline::analyze_pgns(&pgn_text, cfg).await?;
-    let output = if analyses.len() == 1 {
-        serde_json::to_string_pretty(&analyses[0])?
+    let mut outcomes = timelens_core::analysis::pipeline::analyze_pgns(&pgn_text, cfg).await?;
+    let output = if outcomes.len() == 1 {
+        let analysis = outcomes
+            .remove(0)
+            .into_result()
+            .map_err(|failure| anyhow!(failure.error))?;
+        serde_json::to_string_pretty(&analysis)?
     } else {
-        serde_json::to_string_pretty(&analyses)?
+        serde_json::to_string_pretty(&outcomes)?
     };
     if let Some(path) = args.output {
         std::fs::write(path, output)?;
@@ -111,15 +239,109 @@ async fn main() -> Result<()> {
         println!("{}", output);
     }
 
+    let failed = outcomes.iter().filter(|o| report_failure(o)).count();
+    check_failures(failed, outcomes.len())
+}
+
+/// Prints a failed game to stderr; returns whether `outcome` is a failure.
+fn report_failure(outcome: &GameOutcome) -> bool {
+    match outcome {
+        GameOutcome::Failed(failure) => {
+            eprintln!("Game {} failed: {}", failure.game_index + 1, failure.error);
+            true
+        }
+        GameOutcome::Analyzed(_) => false,
+    }
+}
+
+/// Failed games are reported in the output; the run itself only fails when none succeeded.
+fn check_failures(failed: usize, total: usize) -> Result<()> {
+    if total > 0 && failed == total {
+        return Err(anyhow!("All {} games failed to analyse.", total));
+    }
     Ok(())
 }
 
+/// Re-applies the engine and search flags given on the command line, so they win over a profile.
+/// Flags left at their defaults keep the profile's values. `--movetime-ms` and `--nodes` pick the
+/// search mode together, like a profile does.
+fn apply_explicit_engine_flags(cfg: &mut AnalysisConfig, args: &Args, matches: &ArgMatches) {
+    let explicit = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if explicit("engine") {
+        cfg.engine_path = args.engine.clone().unwrap_or_default();
+    }
+    if explicit("depth") {
+        cfg.depth = args.depth;
+    }
+    if explicit("movetime_ms") || explicit("nodes") {
+        cfg.movetime_ms = args.movetime_ms;
+        cfg.nodes = args.nodes;
+    }
+    if explicit("multipv") {
+        cfg.multipv = args.multipv;
+    }
+    if explicit("threads") {
+        cfg.threads = args.threads;
+    }
+    if explicit("hash_mb") {
+        cfg.hash_mb = args.hash_mb;
+    }
+}
+
+/// `--profiles`, then `TIMELENS_PROFILES`, then `~/.timelens/engines.json`.
+fn resolve_profiles_path(override_path: Option<&PathBuf>) -> PathBuf {
+    if let Some(path) = override_path {
+        return path.clone();
+    }
+    if let Ok(path) = std::env::var("TIMELENS_PROFILES") {
+        return PathBuf::from(path);
+    }
+    std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".timelens/engines.json"))
+        .unwrap_or_else(|_| PathBuf::from(".timelens/engines.json"))
+}
+
+/// Writes one JSON object per line as each game finishes, reading `--pgn` files lazily.
+async fn stream_analyses(args: &Args, cfg: AnalysisConfig) -> Result<()> {
+    let mut writer: Box<dyn Write> = match args.output.as_ref() {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut failed = 0usize;
+    let mut write_line = |outcome: GameOutcome| -> Result<()> {
+        if report_failure(&outcome) {
+            failed += 1;
+        }
+        serde_json::to_writer(&mut writer, &outcome)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    };
+
+    let count = if let Some(path) = args.pgn.as_ref() {
+        analyze_game_stream(open_pgn_file(Path::new(path))?, cfg, &mut write_line).await?
+    } else {
+        let text = load_pgn_text(args).await?;
+        analyze_game_stream(read_games(text.as_bytes()), cfg, &mut write_line).await?
+    };
+
+    if count == 0 {
+        return Err(anyhow!("No PGN games found in input"));
+    }
+    check_failures(failed, count)
+}
+
 async fn load_pgn_text(args: &Args) -> Result<String> {
     if let Some(path) = args.pgn.as_ref() {
-        return Ok(std::fs::read_to_string(path)?);
+        let mut text = String::new();
+        open_pgn_source(Path::new(path))?.read_to_string(&mut text)?;
+        return Ok(text);
     }
 
     let cache_dir = fetch::resolve_cache_dir(args.cache_dir.as_ref())?;
+    // Embedded evals are only worth downloading when they will be used.
+    let with_evals = EvalSource::from_name(&args.eval_source) != Some(EvalSource::Engine);
 
     if let Some(user) = args.lichess_user.as_ref() {
         return fetch::fetch_pgn(
@@ -128,6 +350,7 @@ async fn load_pgn_text(args: &Args) -> Result<String> {
             args.games,
             &cache_dir,
             args.refresh_cache,
+            with_evals,
         )
         .await;
     }
@@ -139,6 +362,7 @@ async fn load_pgn_text(args: &Args) -> Result<String> {
             args.games,
             &cache_dir,
             args.refresh_cache,
+            with_evals,
         )
         .await;
     }
@@ -0,0 +1,103 @@
+#![cfg(unix)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const GAME: &str = "[Event \"Profiles\"]\n[Result \"*\"]\n\n1. e4 *\n";
+
+/// A minimal UCI engine that identifies itself as `name`.
+fn fake_engine(dir: &Path, name: &str) -> PathBuf {
+    let script = format!(
+        r#"#!/bin/sh
+while read -r cmd; do
+  case "$cmd" in
+    uci)
+      echo "id name {name}"
+      echo "option name Threads type spin default 1 min 1 max 512"
+      echo "option name Hash type spin default 16 min 1 max 33554432"
+      echo "uciok" ;;
+    isready) echo "readyok" ;;
+    go*)
+      echo "info depth 1 seldepth 1 multipv 1 score cp 20 nodes 10 nps 100 pv e2e4"
+      echo "bestmove e2e4" ;;
+    quit) exit 0 ;;
+  esac
+done
+"#
+    );
+    let path = dir.join(format!("{}.sh", name));
+    std::fs::write(&path, script).expect("write engine");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).expect("chmod");
+    path
+}
+
+/// Runs the CLI on `GAME` with `extra` arguments and returns the analysis metadata.
+fn run(dir: &Path, extra: &[&str]) -> serde_json::Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_timelens-cli"))
+        .arg("--pgn")
+        .arg(dir.join("game.pgn"))
+        .arg("--profiles")
+        .arg(dir.join("engines.json"))
+        .args(["--profile", "ci", "--no-eval-cache"])
+        .args(extra)
+        .output()
+        .expect("run cli");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let analysis: serde_json::Value = serde_json::from_slice(&output.stdout).expect("json");
+    analysis["meta"].clone()
+}
+
+#[test]
+fn explicit_flags_win_over_the_profile() {
+    let dir = std::env::temp_dir().join(format!("timelens_cli_profiles_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("dir");
+    std::fs::write(dir.join("game.pgn"), GAME).expect("pgn");
+    let profile_engine = fake_engine(&dir, "ProfileFish");
+    let flag_engine = fake_engine(&dir, "FlagFish");
+    let profiles = serde_json::json!({
+        "profiles": {
+            "ci": {
+                "path": profile_engine,
+                "depth": 3,
+                "multipv": 2,
+                "threads": 1,
+                "hash_mb": 32
+            }
+        }
+    });
+    std::fs::write(dir.join("engines.json"), profiles.to_string()).expect("profiles");
+
+    let from_profile = run(&dir, &[]);
+    let flag_engine = flag_engine.display().to_string();
+    let overridden = run(
+        &dir,
+        &[
+            "--engine",
+            &flag_engine,
+            "--movetime-ms",
+            "20",
+            "--multipv",
+            "1",
+            "--threads",
+            "2",
+        ],
+    );
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(from_profile["engine"]["name"], "ProfileFish");
+    assert_eq!(from_profile["search_limits"]["depth"], 3);
+    assert_eq!(from_profile["search_limits"]["multipv"], 2);
+    assert_eq!(from_profile["engine"]["options"]["Threads"], "1");
+
+    assert_eq!(overridden["engine"]["name"], "FlagFish");
+    assert_eq!(overridden["search_limits"]["movetime_ms"], 20);
+    assert_eq!(overridden["search_limits"]["multipv"], 1);
+    assert_eq!(overridden["engine"]["options"]["Threads"], "2");
+    // Flags left at their defaults keep the profile's values.
+    assert_eq!(overridden["engine"]["options"]["Hash"], "32");
+}
@@ -0,0 +1,183 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::model::EngineSummary;
+pub use crate::model::SearchLimits;
+
+/// Bumped whenever the file layout, the key format or the meaning of a stored summary changes;
+/// older files are discarded on open.
+const CACHE_VERSION: u32 = 5;
+const CACHE_FILE: &str = "engine_evals.json";
+
+pub const DEFAULT_MAX_ENTRIES: usize = 200_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    limits: SearchLimits,
+    summary: EngineSummary,
+    last_used: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    tick: u64,
+    entries: HashMap<String, CacheEntry>,
+}
+
+/// Engine summaries keyed by engine identity and position key, either persisted across runs or
+/// held in memory. Only the deepest search per key is kept; once an insert goes past
+/// `max_entries`, the least recently used tenth is dropped.
+#[derive(Debug)]
+pub struct EvalCache {
+    path: Option<PathBuf>,
+    max_entries: usize,
+    tick: u64,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl EvalCache {
+    /// Loads `engine_evals.json` from `dir`. A missing, unreadable or outdated file starts an
+    /// empty cache.
+    pub fn open(dir: &Path, max_entries: usize) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Failed to create cache dir {}: {}", dir.display(), e))?;
+        let path = dir.join(CACHE_FILE);
+        let file = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<CacheFile>(&bytes).ok())
+            .filter(|file| file.version == CACHE_VERSION)
+            .unwrap_or_default();
+        let mut cache = Self {
+            path: Some(path),
+            max_entries,
+            tick: file.tick,
+            entries: file.entries,
+        };
+        cache.evict_to(max_entries);
+        Ok(cache)
+    }
+
+    /// A cache that is never written; `save` is a no-op.
+    pub fn in_memory(max_entries: usize) -> Self {
+        Self {
+            path: None,
+            max_entries,
+            tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn key(engine_id: &str, position: &str) -> String {
+        format!("{}|{}", engine_id, position)
+    }
+
+    /// A stored summary covering `limits`, trimmed to `limits.multipv` lines.
+    pub fn get(&mut self, key: &str, limits: &SearchLimits) -> Option<EngineSummary> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        if !entry.limits.covers(limits) {
+            return None;
+        }
+        entry.last_used = self.tick;
+        let mut summary = entry.summary.clone();
+        summary.lines.retain(|l| l.multipv <= limits.multipv);
+        Some(summary)
+    }
+
+    /// Stores `summary` unless the existing entry already covers it.
+    pub fn insert(&mut self, key: String, limits: SearchLimits, summary: EngineSummary) {
+        self.tick += 1;
+        if let Some(existing) = self.entries.get(&key) {
+            if existing.limits.covers(&limits) {
+                return;
+            }
+        }
+        self.entries.insert(
+            key,
+            CacheEntry {
+                limits,
+                summary,
+                last_used: self.tick,
+            },
+        );
+        if self.entries.len() > self.max_entries {
+            // Evicting a batch at a time keeps inserts into a full cache from sorting every time.
+            self.evict_to(self.max_entries - self.max_entries / 10);
+        }
+    }
+
+    /// Drops the least recently used entries until at most `keep` remain.
+    fn evict_to(&mut self, keep: usize) {
+        if self.entries.len() <= keep {
+            return;
+        }
+        let mut by_age: Vec<(u64, String)> = self
+            .entries
+            .iter()
+            .map(|(key, entry)| (entry.last_used, key.clone()))
+            .collect();
+        by_age.sort_unstable();
+        let excess = self.entries.len() - keep;
+        for (_, key) in by_age.into_iter().take(excess) {
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Inserts every entry of `other`, least recently used first.
+    pub fn merge(&mut self, other: EvalCache) {
+        let mut entries: Vec<(String, CacheEntry)> = other.entries.into_iter().collect();
+        entries.sort_unstable_by_key(|(_, entry)| entry.last_used);
+        for (key, entry) in entries {
+            self.insert(key, entry.limits, entry.summary);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.tick = 0;
+    }
+
+    /// Writes the file atomically, through a temporary file no other writer uses.
+    pub fn save(&mut self) -> Result<()> {
+        let Some(path) = self.path.clone() else {
+            return Ok(());
+        };
+
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            tick: self.tick,
+            entries: std::mem::take(&mut self.entries),
+        };
+        let written = serde_json::to_vec(&file)
+            .map_err(|e| anyhow!(e))
+            .and_then(|bytes| {
+                let tmp = temp_path(&path);
+                std::fs::write(&tmp, bytes)?;
+                std::fs::rename(&tmp, &path).inspect_err(|_| {
+                    std::fs::remove_file(&tmp).ok();
+                })?;
+                Ok(())
+            });
+        self.entries = file.entries;
+        written.map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// `engine_evals.json.<pid>-<n>.tmp`, unique across processes and across saves in this one.
+fn temp_path(path: &Path) -> PathBuf {
+    static SAVES: AtomicU64 = AtomicU64::new(0);
+    let n = SAVES.fetch_add(1, Ordering::Relaxed);
+    path.with_extension(format!("json.{}-{}.tmp", std::process::id(), n))
+}
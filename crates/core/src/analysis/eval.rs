@@ -1,5 +1,5 @@
-use crate::model::{Color, EngineSummary};
-use crate::utils::mover_cp;
+use crate::model::{Color, EngineSummary, Score, Wdl};
+use crate::pgn::EmbeddedEval;
 
 pub fn normalize_cp_white(cp_raw: i32, side_to_move: Color) -> i32 {
     match side_to_move {
@@ -14,6 +14,9 @@ pub fn normalize_summary_for_white(summary: &mut EngineSummary, side_to_move: Co
         if let Some(mate) = line.mate.as_mut() {
             *mate = normalize_cp_white(*mate, side_to_move);
         }
+        if side_to_move == Color::Black {
+            line.wdl = line.wdl.map(Wdl::flipped);
+        }
     }
 
     if let Some(cp) = summary.played_cp_white {
@@ -23,27 +26,32 @@ pub fn normalize_summary_for_white(summary: &mut EngineSummary, side_to_move: Co
     if let Some(cp) = summary.best_cp_white {
         summary.best_cp_white = Some(normalize_cp_white(cp, side_to_move));
     }
+
+    summary.played_score_white = summary
+        .played_score_white
+        .map(|s| s.for_mover(side_to_move));
+    summary.best_score_white = summary.best_score_white.map(|s| s.for_mover(side_to_move));
 }
 
+/// Fills the mover-relative metrics. Mates go through `Score::metric_cp`: a forced mate counts as
+/// `MATE_METRIC_CP`, so missing one or walking into one is a full-size swing while picking a
+/// slower mate, or a longer defence against one, costs nothing.
 pub fn fill_engine_metrics(summary: &mut EngineSummary, mover: Color) {
-    if let Some(best) = summary.lines.first().map(|l| l.cp_white) {
-        summary.best_cp_white = Some(best);
+    if let Some(best) = summary.lines.first() {
+        summary.best_cp_white = Some(best.cp_white);
+        summary.best_score_white = Some(best.score());
     }
 
     if summary.lines.len() >= 2 {
-        let best = summary.lines[0].cp_white;
-        let second = summary.lines[1].cp_white;
-        let kth = summary.lines[summary.lines.len() - 1].cp_white;
-        let spread = mover_cp(best, mover) - mover_cp(kth, mover);
-        summary.spread_k_cp_mover = Some(spread);
-
-        let gap12 = mover_cp(best, mover) - mover_cp(second, mover);
-        summary.gap_12_cp_mover = Some(gap12);
+        let best = summary.lines[0].score();
+        let second = summary.lines[1].score();
+        let kth = summary.lines[summary.lines.len() - 1].score();
+        summary.spread_k_cp_mover = Some(score_gap(best, kth, mover));
+        summary.gap_12_cp_mover = Some(score_gap(best, second, mover));
     }
 
-    if let (Some(best), Some(played)) = (summary.best_cp_white, summary.played_cp_white) {
-        let punish = mover_cp(best, mover) - mover_cp(played, mover);
-        summary.punish_cp_mover = Some(punish);
+    if let (Some(best), Some(played)) = (summary.best_score_white, summary.played_score_white) {
+        summary.punish_cp_mover = Some(score_gap(best, played, mover));
     }
 
     summary.complexity_cp_mover = combine_complexity([
@@ -53,6 +61,36 @@ pub fn fill_engine_metrics(summary: &mut EngineSummary, mover: Color) {
     ]);
 }
 
+/// Builds a summary from PGN-annotated evals. `before` is the annotation on the previous ply,
+/// `after` the one on the move itself, so the played move's value needs no extra search.
+pub fn summary_from_embedded(
+    before: Option<EmbeddedEval>,
+    after: Option<EmbeddedEval>,
+    mover: Color,
+) -> EngineSummary {
+    let mut summary = EngineSummary {
+        depth: 0,
+        nodes: 0,
+        nps: 0,
+        lines: Vec::new(),
+        played_cp_white: after.map(EmbeddedEval::cp_white),
+        best_cp_white: before.map(EmbeddedEval::cp_white),
+        played_score_white: after.map(EmbeddedEval::score_white),
+        best_score_white: before.map(EmbeddedEval::score_white),
+        punish_cp_mover: None,
+        spread_k_cp_mover: None,
+        gap_12_cp_mover: None,
+        complexity_cp_mover: None,
+    };
+    fill_engine_metrics(&mut summary, mover);
+    summary
+}
+
+/// How much better `better` is than `worse` for `mover`, in `Score::metric_cp` terms.
+pub fn score_gap(better: Score, worse: Score, mover: Color) -> i32 {
+    better.for_mover(mover).metric_cp() - worse.for_mover(mover).metric_cp()
+}
+
 fn combine_complexity(values: [Option<i32>; 3]) -> Option<i32> {
     let mut best: Option<i32> = None;
     for v in values.into_iter().flatten() {
@@ -1,6 +1,8 @@
-use crate::model::{Label, LabelKind};
+use serde::Serialize;
 
-#[derive(Debug, Clone)]
+use crate::model::{format_san_line, Label, LabelKind, Score};
+
+#[derive(Debug, Clone, Serialize)]
 pub struct LabelConfig {
     pub overthink_ratio: f32,
     pub underthink_ratio: f32,
@@ -12,6 +14,9 @@ pub struct LabelConfig {
     pub big_punish: i32,
     pub snap_punish: i32,
     pub time_blunder_drop: f32,
+    /// Ignore think times and clocks on plies flagged in `ClockDiagnostics` when labelling and
+    /// summarising.
+    pub exclude_suspect_clocks: bool,
 }
 
 impl Default for LabelConfig {
@@ -27,14 +32,90 @@ impl Default for LabelConfig {
             big_punish: 150,
             snap_punish: 250,
             time_blunder_drop: -0.10,
+            exclude_suspect_clocks: false,
         }
     }
 }
 
+/// Plies of the engine's refutation quoted in a label explanation.
+const REFUTATION_QUOTE_PLIES: usize = 4;
+
+/// Appends the opponent's best line to labels that blame the move itself. `first_ply_index` is
+/// the ply index of the first reply in `line_san`.
+pub fn quote_refutation(label: &mut Label, first_ply_index: u32, line_san: &[String]) {
+    if !blames_move(label.kind) || line_san.is_empty() {
+        return;
+    }
+    label.explanation.push_str(&format!(
+        "; refuted after {}",
+        format_san_line(first_ply_index, quoted_plies(line_san))
+    ));
+}
+
+/// Flags a move that gave up a forced mate; `best` and `played` are from the mover's side and
+/// `mating_line_san` is the best line, starting at the move's own `ply_index`. Labels that
+/// already blame the move on the clock keep their kind and just mention the mate.
+pub fn note_missed_mate(
+    label: &mut Label,
+    best: Score,
+    played: Score,
+    ply_index: u32,
+    mating_line_san: &[String],
+) {
+    let Some(moves) = best.mate_in() else {
+        return;
+    };
+    if played.mate_in().is_some() {
+        return;
+    }
+    if blames_move(label.kind) {
+        label
+            .explanation
+            .push_str(&format!("; missed mate in {}", moves));
+        return;
+    }
+
+    let line = if mating_line_san.is_empty() {
+        String::new()
+    } else {
+        format!(
+            ": {}",
+            format_san_line(ply_index, quoted_plies(mating_line_san))
+        )
+    };
+    label.kind = LabelKind::MissedMate;
+    label.title = "Missed a forced mate".to_string();
+    label.severity = label.severity.max(0.7);
+    label.explanation = format!(
+        "Mate in {} was available{}. {}",
+        moves, line, label.explanation
+    );
+    label
+        .tips
+        .push("With the enemy king exposed, check every forcing move before settling.".to_string());
+}
+
+/// Labels that blame the move itself rather than the clock situation around it.
+fn blames_move(kind: LabelKind) -> bool {
+    matches!(
+        kind,
+        LabelKind::PremoveBlunder
+            | LabelKind::SnapBlunder
+            | LabelKind::PanicBlunder
+            | LabelKind::WastedThink
+            | LabelKind::UnderthinkCritical
+    )
+}
+
+fn quoted_plies(line_san: &[String]) -> &[String] {
+    &line_san[..line_san.len().min(REFUTATION_QUOTE_PLIES)]
+}
+
 pub fn label_move(
     cfg: &LabelConfig,
     think_time: Option<f32>,
     t_rem_before: Option<f32>,
+    premove: bool,
     punish_cp_mover: Option<i32>,
     complexity_cp_mover: Option<i32>,
     dp_practical_mover: f32,
@@ -60,12 +141,15 @@ pub fn label_move(
 
     let wasted = spent > (cfg.overthink_ratio * t_rem) && punish > cfg.big_punish;
     let snap = spent < cfg.min_snap_secs && punish > cfg.snap_punish;
+    let premove_blunder = premove && punish > cfg.snap_punish;
     let panic_blunder = in_panic && punish > cfg.big_punish;
     let time_blunder = in_time_trouble
         && dp_practical_mover < cfg.time_blunder_drop
         && punish < cfg.max_simple_complexity;
 
-    let (kind, title) = if snap {
+    let (kind, title) = if premove_blunder {
+        (LabelKind::PremoveBlunder, "Premove blunder")
+    } else if snap {
         (LabelKind::SnapBlunder, "Snap blunder")
     } else if panic_blunder {
         (LabelKind::PanicBlunder, "Panic blunder")
@@ -76,7 +160,10 @@ pub fn label_move(
     } else if overthink {
         (LabelKind::OverthinkSimple, "Overthinking a simple position")
     } else if underthink {
-        (LabelKind::UnderthinkCritical, "Underthinking a critical moment")
+        (
+            LabelKind::UnderthinkCritical,
+            "Underthinking a critical moment",
+        )
     } else if dp_practical_mover > 0.05 && complex > cfg.critical_complexity {
         (LabelKind::GoodInvestment, "Good investment")
     } else if in_time_trouble {
@@ -85,7 +172,9 @@ pub fn label_move(
         (LabelKind::Neutral, "Neutral")
     };
 
-    let severity = if snap || panic_blunder {
+    let severity = if premove_blunder {
+        0.8
+    } else if snap || panic_blunder {
         0.9
     } else if time_blunder {
         0.7
@@ -109,6 +198,9 @@ pub fn label_move(
         "In blitz, spend time where the position is knife-edge; play instantly where it's not."
             .to_string(),
     ];
+    if premove_blunder {
+        tips.push("Only premove forced replies; check what the opponent can do first.".to_string());
+    }
     if in_time_trouble {
         tips.push(format!(
             "Try to keep at least {:.0}s before critical moments.",
@@ -1,5 +1,7 @@
+pub mod cache;
 pub mod eval;
 pub mod labeling;
 pub mod pipeline;
 pub mod position;
+pub mod profile;
 pub mod time_equity;
@@ -1,34 +1,111 @@
-use anyhow::{anyhow, Result};
-use std::collections::HashMap;
-
-use crate::analysis::eval::{fill_engine_metrics, normalize_summary_for_white};
-use crate::analysis::labeling::{label_move, LabelConfig};
-use crate::analysis::position::build_ply_records_with_fens;
-use crate::analysis::time_equity::{mover_prob, time_equity_white_cp, win_prob_from_cp};
-use crate::clocks::derive_clock_before_and_think_times;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use shakmaty::{variant::Variant, CastlingMode};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::task::JoinSet;
+
+use crate::analysis::cache::{EvalCache, SearchLimits, DEFAULT_MAX_ENTRIES};
+use crate::analysis::eval::{
+    fill_engine_metrics, normalize_summary_for_white, score_gap, summary_from_embedded,
+};
+use crate::analysis::labeling::{label_move, note_missed_mate, quote_refutation, LabelConfig};
+use crate::analysis::position::{build_ply_records_and_warnings, position_key, pv_to_san};
+use crate::analysis::time_equity::{
+    armageddon_white_prob, mover_prob, shift_prob_by_cp, time_equity_white_cp, win_prob_from_cp,
+};
+use crate::clocks::{
+    derive_clock_before_and_think_times, infer_time_control, interpolate_missing_clocks,
+    MIN_INFERRED_CONFIDENCE,
+};
 use crate::engine::uci::UciEngine;
+use crate::engine::{EngineError, EngineTimeouts};
 use crate::model::{
-    Color, EngineSummary, GameAnalysis, GameMeta, GameSummary, MoveMetrics, PhaseAverages,
-    PhaseTimeShare, PhaseTimeShareDelta, PlyAnalysis, PHASE_MIDDLEGAME_END_PLY,
-    PHASE_OPENING_END_PLY,
+    move_number_for_ply, AlternativeMove, ClockDiagnostics, Color, EngineLine, EngineSummary,
+    GameAnalysis, GameFailure, GameMeta, GameOutcome, GameSummary, MoveMetrics, PhaseAverages,
+    PhaseTimeShare, PhaseTimeShareDelta, PlyAnalysis, Score, TimeControlSource, TimeDelay, Wdl,
+    PHASE_MIDDLEGAME_END_PLY, PHASE_OPENING_END_PLY,
 };
-use crate::pgn::{detect_platform, parse_games, parse_time_control_header};
+use crate::pgn::{
+    detect_armageddon, detect_chess960, detect_platform, detect_variant, parse_games,
+    parse_starting_clocks, parse_time_control_header, EmbeddedEval, ParsedGame,
+};
+use crate::utils::fnv1a_64;
+
+/// Where position evaluations come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum EvalSource {
+    /// Search every position with the UCI engine.
+    Engine,
+    /// Use `[%eval]` annotations only; the engine is never started.
+    Embedded,
+    /// Use annotations where present and search the remaining positions.
+    Mixed,
+}
+
+impl EvalSource {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "engine" => Some(Self::Engine),
+            "embedded" => Some(Self::Embedded),
+            "mixed" => Some(Self::Mixed),
+            _ => None,
+        }
+    }
 
-#[derive(Debug, Clone)]
+    fn needs_engine(self) -> bool {
+        self != Self::Embedded
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct AnalysisConfig {
     pub engine_path: String,
     pub multipv: u8,
     pub depth: u16,
     pub movetime_ms: Option<u64>,
+    /// Node-limited search (`go nodes`), overriding `movetime_ms` and `depth`. The engine runs
+    /// single-threaded and starts each search from an empty hash table, so the evaluations are the
+    /// same on every machine and whatever was searched before.
+    pub nodes: Option<u64>,
     pub threads: Option<u32>,
     pub hash_mb: Option<u32>,
+    /// Further UCI options (`SyzygyPath`, `EvalFile`, `UCI_Elo`, ...), sent after `Threads` and
+    /// `Hash`. Each must be advertised by the engine.
+    pub engine_options: BTreeMap<String, String>,
+    pub engine_timeouts: EngineTimeouts,
+    /// Engine restarts allowed per game when the engine crashes or stalls; the failing position
+    /// is retried after each. A game that runs out of restarts is reported as failed.
+    pub engine_restarts: u32,
+    /// Engine processes analysing games in parallel; each gets `threads` and `hash_mb`.
+    pub engine_pool_size: usize,
+    /// Directory of the persistent engine evaluation cache; `None` disables it.
+    pub eval_cache_dir: Option<PathBuf>,
+    pub eval_cache_max_entries: usize,
+    /// Discard the persistent cache before analysing.
+    pub refresh_eval_cache: bool,
+    pub eval_source: EvalSource,
+    pub analyze_variations: bool,
     pub fallback_time_control: Option<crate::model::TimeControl>,
+    /// Overrides the delay of whichever time control applies (header or fallback).
+    pub delay: Option<TimeDelay>,
+    /// Starting clocks overriding `WhiteClock`/`BlackClock` headers and the time control base.
+    pub white_clock_secs: Option<u32>,
+    pub black_clock_secs: Option<u32>,
+    /// Treat every game as Armageddon, even without an `Armageddon` header or event name.
+    pub armageddon: bool,
+    /// Estimate think times across plies with missing clock readings.
+    pub interpolate_clocks: bool,
     pub alpha: f32,
     pub beta: f32,
     pub time_pressure_pivot: f32,
     pub time_pressure_scale: f32,
     pub time_pressure_boost: f32,
     pub k_sigmoid: f32,
+    /// Take win probabilities from the engine's WDL output where reported, falling back to the
+    /// `k_sigmoid` curve.
+    pub use_wdl: bool,
     pub label_config: LabelConfig,
 }
 
@@ -39,20 +116,56 @@ impl Default for AnalysisConfig {
             multipv: 4,
             depth: 14,
             movetime_ms: None,
+            nodes: None,
             threads: None,
             hash_mb: None,
+            engine_options: BTreeMap::new(),
+            engine_timeouts: EngineTimeouts::default(),
+            engine_restarts: 2,
+            engine_pool_size: 1,
+            eval_cache_dir: None,
+            eval_cache_max_entries: DEFAULT_MAX_ENTRIES,
+            refresh_eval_cache: false,
+            eval_source: EvalSource::Engine,
+            analyze_variations: false,
             fallback_time_control: None,
+            delay: None,
+            white_clock_secs: None,
+            black_clock_secs: None,
+            armageddon: false,
+            interpolate_clocks: false,
             alpha: 2.0,
             beta: 10.0,
             time_pressure_pivot: 30.0,
             time_pressure_scale: 8.0,
             time_pressure_boost: 3.0,
             k_sigmoid: 1.2,
+            use_wdl: true,
             label_config: LabelConfig::default(),
         }
     }
 }
 
+impl AnalysisConfig {
+    /// Fingerprint of every setting that can change the output. The engine path and the cache,
+    /// pool and recovery settings are left out: they change how results are obtained, not what
+    /// is asked for.
+    pub fn config_hash(&self) -> String {
+        let comparable = AnalysisConfig {
+            engine_path: String::new(),
+            engine_timeouts: EngineTimeouts::default(),
+            engine_restarts: 0,
+            engine_pool_size: 1,
+            eval_cache_dir: None,
+            eval_cache_max_entries: DEFAULT_MAX_ENTRIES,
+            refresh_eval_cache: false,
+            ..self.clone()
+        };
+        let json = serde_json::to_vec(&comparable).unwrap_or_default();
+        format!("{:016x}", fnv1a_64(&json))
+    }
+}
+
 pub async fn analyze_pgn(pgn: &str, cfg: AnalysisConfig) -> Result<GameAnalysis> {
     let games = parse_games(pgn)?;
     if games.len() != 1 {
@@ -62,83 +175,783 @@ pub async fn analyze_pgn(pgn: &str, cfg: AnalysisConfig) -> Result<GameAnalysis>
         ));
     }
 
-    let mut engine = start_engine(&cfg).await?;
-    engine.new_game().await?;
-    let result = analyze_parsed_game(games.into_iter().next().unwrap(), &cfg, &mut engine).await;
-    let shutdown_result = engine.shutdown().await;
+    let game = games.into_iter().next().unwrap();
+    let store = EvalStore::open(&cfg)?;
+    let rules = GameRules::from_headers(&game.headers);
+    let mut worker = EngineWorker::start(Arc::new(cfg), rules).await?;
+    let result = worker.analyze(game, store.as_ref()).await;
+    let shutdown_result = worker.shutdown().await;
+    let save_result = store.as_ref().map(EvalStore::save).unwrap_or(Ok(()));
     match (result, shutdown_result) {
-        (Ok(analysis), Ok(())) => Ok(analysis),
+        (Ok(analysis), Ok(())) => save_result.map(|()| analysis),
         (Err(err), _) => Err(err),
         (Ok(_), Err(err)) => Err(anyhow!(err)),
     }
 }
 
-pub async fn analyze_pgns(pgn: &str, cfg: AnalysisConfig) -> Result<Vec<GameAnalysis>> {
+/// Analyses every game of `pgn`. A game that fails, e.g. on an illegal move or an engine that
+/// keeps crashing, becomes a `GameOutcome::Failed` entry and the rest of the batch continues.
+pub async fn analyze_pgns(pgn: &str, cfg: AnalysisConfig) -> Result<Vec<GameOutcome>> {
     let games = parse_games(pgn)?;
     if games.is_empty() {
         return Err(anyhow!("No PGN games found in input"));
     }
 
-    let mut engine = start_engine(&cfg).await?;
     let mut out = Vec::with_capacity(games.len());
-    for game in games {
+    analyze_game_stream(games.into_iter().map(Ok), cfg, |outcome| {
+        out.push(outcome);
+        Ok(())
+    })
+    .await?;
+    Ok(out)
+}
+
+/// Analyses games as they are pulled from `games` (e.g. `pgn::read_games`) and hands each
+/// result to `on_game` as soon as it and every earlier game have finished. Games are spread over
+/// `cfg.engine_pool_size` engine processes; each game starts from `ucinewgame`. Node-limited
+/// results match a sequential run; in other modes only a sequential run reuses one game's searches
+/// in the next. Games that fail are handed over as `GameOutcome::Failed`; only input errors,
+/// an engine that cannot be started and errors from `on_game` end the stream. Returns the number
+/// of games handed over.
+pub async fn analyze_game_stream<I, F>(games: I, cfg: AnalysisConfig, on_game: F) -> Result<usize>
+where
+    I: IntoIterator<Item = Result<ParsedGame>>,
+    F: FnMut(GameOutcome) -> Result<()>,
+{
+    let store = EvalStore::open(&cfg)?;
+    let result = run_game_stream(games, Arc::new(cfg), store.clone(), on_game).await;
+    let save_result = store.as_ref().map(EvalStore::save).unwrap_or(Ok(()));
+    let count = result?;
+    save_result.map(|()| count)
+}
+
+async fn run_game_stream<I, F>(
+    games: I,
+    cfg: Arc<AnalysisConfig>,
+    store: Option<EvalStore>,
+    mut on_game: F,
+) -> Result<usize>
+where
+    I: IntoIterator<Item = Result<ParsedGame>>,
+    F: FnMut(GameOutcome) -> Result<()>,
+{
+    let mut pool = GamePool::new(cfg, store);
+    let result = match pool.feed(games, &mut on_game).await {
+        Ok(()) => pool.drain(&mut on_game).await,
+        Err(err) => Err(err),
+    };
+    let shutdown_result = pool.shutdown().await;
+    let count = result?;
+    shutdown_result?;
+    Ok(count)
+}
+
+/// The games being analysed, the workers free to take the next one and the finished games
+/// waiting for an earlier one to be handed over.
+struct GamePool {
+    cfg: Arc<AnalysisConfig>,
+    store: Option<EvalStore>,
+    size: usize,
+    started: usize,
+    idle: Vec<EngineWorker>,
+    in_flight: JoinSet<(usize, EngineWorker, GameOutcome)>,
+    /// Input index and headers of each running task, to report a game whose task panicked.
+    running: HashMap<tokio::task::Id, (usize, HashMap<String, String>)>,
+    finished: BTreeMap<usize, GameOutcome>,
+    emitted: usize,
+}
+
+impl GamePool {
+    fn new(cfg: Arc<AnalysisConfig>, store: Option<EvalStore>) -> Self {
+        Self {
+            size: cfg.engine_pool_size.max(1),
+            cfg,
+            store,
+            started: 0,
+            idle: Vec::new(),
+            in_flight: JoinSet::new(),
+            running: HashMap::new(),
+            finished: BTreeMap::new(),
+            emitted: 0,
+        }
+    }
+
+    async fn feed<I, F>(&mut self, games: I, on_game: &mut F) -> Result<()>
+    where
+        I: IntoIterator<Item = Result<ParsedGame>>,
+        F: FnMut(GameOutcome) -> Result<()>,
+    {
+        for (idx, game) in games.into_iter().enumerate() {
+            let game = game?;
+            // Wait for a free worker, and stop reading ahead while a slow early game holds back
+            // more finished games than there are workers.
+            while (self.idle.is_empty() && self.started == self.size)
+                || self.finished.len() > self.size
+            {
+                self.join_next(on_game).await?;
+            }
+            let worker = match self.idle.pop() {
+                Some(worker) => worker,
+                None => {
+                    self.started += 1;
+                    let rules = GameRules::from_headers(&game.headers);
+                    EngineWorker::start(Arc::clone(&self.cfg), rules).await?
+                }
+            };
+            self.spawn(idx, worker, game);
+        }
+        Ok(())
+    }
+
+    fn spawn(&mut self, idx: usize, mut worker: EngineWorker, game: ParsedGame) {
+        let store = self.store.clone();
+        let running = (idx, game.headers.clone());
+        let task = self.in_flight.spawn(async move {
+            let headers = game.headers.clone();
+            let outcome = match worker.analyze(game, store.as_ref()).await {
+                Ok(analysis) => GameOutcome::Analyzed(Box::new(analysis)),
+                Err(err) => GameOutcome::Failed(GameFailure {
+                    game_index: idx,
+                    error: format!("{:#}", err),
+                    headers,
+                }),
+            };
+            (idx, worker, outcome)
+        });
+        self.running.insert(task.id(), running);
+    }
+
+    /// Waits for every game still running. Returns the number of games handed over.
+    async fn drain<F>(&mut self, on_game: &mut F) -> Result<usize>
+    where
+        F: FnMut(GameOutcome) -> Result<()>,
+    {
+        while !self.in_flight.is_empty() {
+            self.join_next(on_game).await?;
+        }
+        Ok(self.emitted)
+    }
+
+    /// Collects the next finished game and hands over every game that is now next in input
+    /// order.
+    async fn join_next<F>(&mut self, on_game: &mut F) -> Result<()>
+    where
+        F: FnMut(GameOutcome) -> Result<()>,
+    {
+        let joined = match self.in_flight.join_next_with_id().await {
+            Some(joined) => joined,
+            None => return Err(anyhow!("No analysis task in flight")),
+        };
+        let (idx, outcome) = match joined {
+            Ok((id, (idx, worker, outcome))) => {
+                self.running.remove(&id);
+                self.idle.push(worker);
+                (idx, outcome)
+            }
+            Err(err) => {
+                // The worker went down with its task; the next game starts a fresh one.
+                let (idx, headers) = self
+                    .running
+                    .remove(&err.id())
+                    .ok_or_else(|| anyhow!("Analysis task failed: {}", err))?;
+                self.started -= 1;
+                let failure = GameFailure {
+                    game_index: idx,
+                    error: format!("Analysis task failed: {}", err),
+                    headers,
+                };
+                (idx, GameOutcome::Failed(failure))
+            }
+        };
+        self.finished.insert(idx, outcome);
+        while let Some(outcome) = self.finished.remove(&self.emitted) {
+            on_game(outcome)?;
+            self.emitted += 1;
+        }
+        Ok(())
+    }
+
+    /// Stops games still running after an early error (their engines are killed on drop) and
+    /// sends `quit` to every idle engine.
+    async fn shutdown(mut self) -> Result<(), EngineError> {
+        self.in_flight.abort_all();
+        while let Some(joined) = self.in_flight.join_next().await {
+            if let Ok((_, worker, _)) = joined {
+                self.idle.push(worker);
+            }
+        }
+        let mut result = Ok(());
+        for worker in self.idle {
+            let shutdown = worker.shutdown().await;
+            result = result.and(shutdown);
+        }
+        result
+    }
+}
+
+/// One engine process of the pool. It is restarted when it crashes or stalls, and replaced by a
+/// fresh process after a game fails.
+struct EngineWorker {
+    cfg: Arc<AnalysisConfig>,
+    engine: Option<UciEngine>,
+    rules: Option<GameRules>,
+    /// Restarts during the current game.
+    restarts: u32,
+}
+
+impl EngineWorker {
+    /// Starts the engine (when the evaluation source needs one) set up for `rules`. Failing here
+    /// usually means a bad path or option, so it ends the run rather than a single game.
+    async fn start(cfg: Arc<AnalysisConfig>, rules: GameRules) -> Result<Self> {
+        let engine = if cfg.eval_source.needs_engine() {
+            Some(start_engine(&cfg, rules).await?)
+        } else {
+            None
+        };
+        Ok(Self {
+            cfg,
+            engine,
+            rules: Some(rules),
+            restarts: 0,
+        })
+    }
+
+    async fn analyze(
+        &mut self,
+        game: ParsedGame,
+        store: Option<&EvalStore>,
+    ) -> Result<GameAnalysis> {
+        let cfg = Arc::clone(&self.cfg);
+        let game_rules = GameRules::from_headers(&game.headers);
+        self.restarts = 0;
+        let store = store.map(|s| s.for_game(game_rules));
+        let result = match self.begin_game(game_rules).await {
+            Ok(()) => analyze_parsed_game(game, &cfg, self, store.as_ref()).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = &result {
+            // After an engine failure the process may be mid-search or hung, so the next game
+            // gets a fresh one. Other failures (a bad PGN, say) leave the engine idle and usable.
+            if err.chain().any(|cause| cause.is::<EngineError>()) {
+                self.engine = None;
+            }
+        }
+        result
+    }
+
+    async fn begin_game(&mut self, rules: GameRules) -> Result<()> {
+        if !self.cfg.eval_source.needs_engine() {
+            return Ok(());
+        }
+        let previous = self.rules.replace(rules);
+        let ready = match self.engine.as_mut() {
+            Some(engine) => match rules.apply(engine, previous).await {
+                Ok(()) => engine.new_game().await.map_err(anyhow::Error::from),
+                Err(err) => Err(err),
+            },
+            None => return self.replace_engine().await,
+        };
+        match ready {
+            Ok(()) => Ok(()),
+            Err(err) => self.restart(err).await,
+        }
+    }
+
+    fn has_engine(&self) -> bool {
+        self.engine.is_some()
+    }
+
+    /// Searches `fen`, restarting the engine and retrying the position if it crashes or stalls.
+    async fn search(
+        &mut self,
+        fen: &str,
+        limits: &SearchLimits,
+        searchmoves: Option<&str>,
+    ) -> Result<EngineSummary> {
+        loop {
+            let engine = self
+                .engine
+                .as_mut()
+                .ok_or_else(|| anyhow!("No engine available to analyse {}", fen))?;
+            let attempt = async {
+                // A node-limited search must not depend on what earlier searches left in the
+                // hash table, or a cache hit would change the next result.
+                if limits.nodes.is_some() {
+                    engine.new_game().await?;
+                }
+                engine.position_fen(fen).await?;
+                engine.go_multipv(limits, searchmoves).await
+            }
+            .await;
+            match attempt {
+                Ok(summary) => return Ok(summary),
+                Err(err) => {
+                    let err = anyhow!(err).context(format!("Engine failed on {}", fen));
+                    self.restart(err).await?;
+                }
+            }
+        }
+    }
+
+    /// Replaces the engine with a fresh process, unless this game has used up its restarts, in
+    /// which case `cause` is returned.
+    async fn restart(&mut self, cause: anyhow::Error) -> Result<()> {
+        if self.restarts >= self.cfg.engine_restarts {
+            return Err(cause);
+        }
+        self.restarts += 1;
+        self.replace_engine()
+            .await
+            .with_context(|| format!("Engine restart failed after: {:#}", cause))
+    }
+
+    async fn replace_engine(&mut self) -> Result<()> {
+        // Dropping the old process kills it.
+        self.engine = None;
+        let mut engine = start_engine(&self.cfg, self.rules.unwrap_or_default()).await?;
         engine.new_game().await?;
-        out.push(analyze_parsed_game(game, &cfg, &mut engine).await?);
+        self.engine = Some(engine);
+        Ok(())
     }
-    let shutdown_result = engine.shutdown().await;
-    match shutdown_result {
-        Ok(()) => Ok(out),
-        Err(err) => Err(anyhow!(err)),
+
+    async fn shutdown(self) -> Result<(), EngineError> {
+        match self.engine {
+            Some(engine) => engine.shutdown().await,
+            None => Ok(()),
+        }
     }
 }
 
-async fn start_engine(cfg: &AnalysisConfig) -> Result<UciEngine> {
-    let mut engine = UciEngine::start(&cfg.engine_path).await?;
+/// The evaluations one game can reuse: those persisted by earlier runs (as they stood when the
+/// batch started) and the searches of this run. A sequential run, or a node-limited one (which
+/// clears the hash before every search), shares its searches across the batch, so a common opening
+/// is searched once. A pooled depth or movetime run keeps them per game: which game gets to a
+/// shared position first depends on the schedule, and a hit skips a search whose hash entries the
+/// rest of the game would have used. Both caches hold at most `eval_cache_max_entries`.
+#[derive(Clone)]
+struct EvalStore {
+    persisted: Option<Arc<Mutex<EvalCache>>>,
+    /// This run's searches, merged into `persisted` on save.
+    found: Option<Arc<Mutex<EvalCache>>>,
+    /// This run's searches that the current game may reuse.
+    game: Arc<Mutex<EvalCache>>,
+    share_across_games: bool,
+    max_entries: usize,
+    engine_id: String,
+    rules: GameRules,
+}
+
+impl EvalStore {
+    fn open(cfg: &AnalysisConfig) -> Result<Option<Self>> {
+        if !cfg.eval_source.needs_engine() {
+            return Ok(None);
+        }
+        let max_entries = cfg.eval_cache_max_entries;
+        let persisted = match cfg.eval_cache_dir.as_ref() {
+            Some(dir) => {
+                let mut cache = EvalCache::open(dir, max_entries)?;
+                if cfg.refresh_eval_cache {
+                    cache.clear();
+                }
+                Some(cache)
+            }
+            None => None,
+        };
+        let shared = |cache| Arc::new(Mutex::new(cache));
+        Ok(Some(Self {
+            found: persisted
+                .as_ref()
+                .map(|_| shared(EvalCache::in_memory(max_entries))),
+            persisted: persisted.map(shared),
+            game: shared(EvalCache::in_memory(max_entries)),
+            share_across_games: cfg.engine_pool_size <= 1 || search_limits(cfg).nodes.is_some(),
+            max_entries,
+            engine_id: engine_identity(cfg),
+            rules: GameRules::default(),
+        }))
+    }
+
+    /// A store for one game under `rules`, with an empty cache of its own unless searches are
+    /// shared across the batch.
+    fn for_game(&self, rules: GameRules) -> Self {
+        let game = if self.share_across_games {
+            Arc::clone(&self.game)
+        } else {
+            Arc::new(Mutex::new(EvalCache::in_memory(self.max_entries)))
+        };
+        Self {
+            persisted: self.persisted.clone(),
+            found: self.found.clone(),
+            game,
+            share_across_games: self.share_across_games,
+            max_entries: self.max_entries,
+            engine_id: format!(
+                "{}|{}{}",
+                self.engine_id,
+                rules.variant.uci(),
+                if rules.chess960 { "|960" } else { "" }
+            ),
+            rules,
+        }
+    }
+
+    fn key(&self, fen: &str) -> Result<String> {
+        let position = position_key(fen, self.rules.variant, self.rules.castling_mode())?;
+        Ok(EvalCache::key(&self.engine_id, &position))
+    }
+
+    fn get(&self, key: &str, limits: &SearchLimits) -> Option<EngineSummary> {
+        lock(&self.game).get(key, limits).or_else(|| {
+            self.persisted
+                .as_ref()
+                .and_then(|persisted| lock(persisted).get(key, limits))
+        })
+    }
+
+    fn insert(&self, key: String, limits: SearchLimits, summary: EngineSummary) {
+        if let Some(found) = self.found.as_ref() {
+            lock(found).insert(key.clone(), limits, summary.clone());
+        }
+        lock(&self.game).insert(key, limits, summary);
+    }
+
+    fn save(&self) -> Result<()> {
+        let (Some(persisted), Some(found)) = (self.persisted.as_ref(), self.found.as_ref()) else {
+            return Ok(());
+        };
+        let found = std::mem::replace(&mut *lock(found), EvalCache::in_memory(self.max_entries));
+        let mut persisted = lock(persisted);
+        persisted.merge(found);
+        persisted.save()
+    }
+}
+
+fn lock(cache: &Mutex<EvalCache>) -> std::sync::MutexGuard<'_, EvalCache> {
+    cache.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Engine path plus binary size and modification time, so upgrading the engine invalidates its
+/// cached evaluations. Threads, hash size and user-set UCI options are appended, since they can
+/// change the evaluation.
+fn engine_identity(cfg: &AnalysisConfig) -> String {
+    let path = cfg.engine_path.as_str();
+    let stamp = std::fs::metadata(resolve_engine_path(path))
+        .ok()
+        .map(|meta| {
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            format!("{}:{}", meta.len(), modified)
+        });
+    let mut id = match stamp {
+        Some(stamp) => format!("{}@{}", path, stamp),
+        None => path.to_string(),
+    };
     if let Some(threads) = cfg.threads {
+        id.push_str(&format!("|Threads={}", threads));
+    }
+    if let Some(hash_mb) = cfg.hash_mb {
+        id.push_str(&format!("|Hash={}", hash_mb));
+    }
+    for (name, value) in &cfg.engine_options {
+        id.push_str(&format!("|{}={}", name, value));
+    }
+    id
+}
+
+/// Where the OS finds the engine: a bare name such as `stockfish` is looked up in `PATH` the way
+/// spawning it would be.
+fn resolve_engine_path(path: &str) -> PathBuf {
+    let given = PathBuf::from(path);
+    if given.components().count() != 1 {
+        return given;
+    }
+    std::env::var_os("PATH")
+        .and_then(|dirs| {
+            std::env::split_paths(&dirs)
+                .flat_map(|dir| {
+                    let with_suffix = format!("{}{}", path, std::env::consts::EXE_SUFFIX);
+                    [dir.join(&given), dir.join(with_suffix)]
+                })
+                .find(|candidate| candidate.is_file())
+        })
+        .unwrap_or(given)
+}
+
+async fn start_engine(cfg: &AnalysisConfig, rules: GameRules) -> Result<UciEngine> {
+    if let Some(threads) = cfg.threads.filter(|&t| cfg.nodes.is_some() && t != 1) {
+        return Err(anyhow!(
+            "Node-limited search needs a single engine thread to be reproducible, not {}.",
+            threads
+        ));
+    }
+    let mut engine = UciEngine::start(&cfg.engine_path, cfg.engine_timeouts).await?;
+    // Node-limited searches pin the engine to one thread whatever its default.
+    let threads = cfg
+        .threads
+        .or_else(|| (cfg.nodes.is_some() && engine.supports_option("Threads")).then_some(1));
+    if let Some(threads) = threads {
         engine.set_option("Threads", &threads.to_string()).await?;
     }
     if let Some(hash_mb) = cfg.hash_mb {
         engine.set_option("Hash", &hash_mb.to_string()).await?;
     }
+    check_engine_options(&engine, &cfg.engine_options)?;
+    for (name, value) in cfg.engine_options.iter() {
+        engine.set_option(name, value).await?;
+    }
     engine
         .set_option("MultiPV", &cfg.multipv.to_string())
         .await?;
+    // Always requested when available, so cached summaries carry WDL whatever `use_wdl` says.
+    if engine.supports_option("UCI_ShowWDL") {
+        engine.set_option("UCI_ShowWDL", "true").await?;
+    }
+    rules.apply(&mut engine, None).await?;
     Ok(engine)
 }
 
+/// Options timelens sets itself, per run or per game.
+const MANAGED_OPTIONS: [&str; 4] = ["MultiPV", "UCI_Variant", "UCI_Chess960", "UCI_ShowWDL"];
+
+/// Rejects options the engine did not advertise, and ones the pipeline manages itself, before
+/// any of them is sent.
+fn check_engine_options(engine: &UciEngine, options: &BTreeMap<String, String>) -> Result<()> {
+    if let Some(managed) = options
+        .keys()
+        .find(|name| MANAGED_OPTIONS.iter().any(|m| m.eq_ignore_ascii_case(name)))
+    {
+        return Err(anyhow!(
+            "UCI option '{}' is set by the analysis itself and cannot be overridden.",
+            managed
+        ));
+    }
+    let unsupported: Vec<&str> = options
+        .keys()
+        .filter(|name| !engine.supports_option(name))
+        .map(String::as_str)
+        .collect();
+    if unsupported.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{} does not offer UCI option(s): {}.",
+        engine.info().name.as_deref().unwrap_or("The engine"),
+        unsupported.join(", ")
+    ))
+}
+
+/// Engine-side rules a game needs: Chess960 castling and, for variant-capable engines such as
+/// Fairy-Stockfish, `UCI_Variant`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct GameRules {
+    variant: Variant,
+    chess960: bool,
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            variant: Variant::Chess,
+            chess960: false,
+        }
+    }
+}
+
+impl GameRules {
+    fn from_headers(headers: &HashMap<String, String>) -> Self {
+        Self {
+            variant: detect_variant(headers).unwrap_or(Variant::Chess),
+            chess960: detect_chess960(headers),
+        }
+    }
+
+    fn castling_mode(self) -> CastlingMode {
+        if self.chess960 {
+            CastlingMode::Chess960
+        } else {
+            CastlingMode::Standard
+        }
+    }
+
+    /// Sends only the options that differ from `previous`; a fresh engine is assumed to be
+    /// standard chess.
+    async fn apply(self, engine: &mut UciEngine, previous: Option<GameRules>) -> Result<()> {
+        let previous = previous.unwrap_or_default();
+        if self.variant != previous.variant {
+            engine
+                .set_game_option("UCI_Variant", self.variant.uci())
+                .await?;
+        }
+        if self.chess960 != previous.chess960 {
+            engine
+                .set_game_option("UCI_Chess960", &self.chess960.to_string())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
 async fn analyze_parsed_game(
     parsed: crate::pgn::ParsedGame,
     cfg: &AnalysisConfig,
-    engine: &mut UciEngine,
+    worker: &mut EngineWorker,
+    store: Option<&EvalStore>,
 ) -> Result<GameAnalysis> {
     let platform = detect_platform(&parsed.headers);
-    let time_control =
-        parse_time_control_header(&parsed.headers).or_else(|| cfg.fallback_time_control.clone());
-
-    let mut plies = build_ply_records_with_fens(&parsed)?;
-    derive_clock_before_and_think_times(&mut plies, time_control.clone(), platform);
-    let meta = build_meta(&parsed, time_control.clone(), platform);
-    let (summaries, last_after_summary) = analyze_engine_summaries(engine, &plies, cfg).await?;
-    let clock_states = derive_clock_states(&plies, time_control);
+    let rules = GameRules::from_headers(&parsed.headers);
+    let armageddon = cfg.armageddon || detect_armageddon(&parsed.headers);
+
+    let (mut plies, warnings) = build_ply_records_and_warnings(&parsed)?;
+    let resolved = resolve_time_control(&parsed.headers, &plies, cfg);
+    let time_control = resolved.time_control.clone();
+    let mut clock_diagnostics =
+        derive_clock_before_and_think_times(&mut plies, time_control.clone(), platform);
+    clock_diagnostics.warnings.extend(resolved.warning.clone());
+    if cfg.delay.is_some() && time_control.is_none() {
+        clock_diagnostics.warnings.push(
+            "The configured delay was not applied: no time control was found in the headers, \
+             the fallback or the clock readings."
+                .to_string(),
+        );
+    }
+    if cfg.interpolate_clocks {
+        interpolate_missing_clocks(&mut plies, time_control.as_ref());
+    }
+    let mut meta = build_meta(&parsed, resolved, armageddon, platform);
+    meta.warnings = warnings;
+    meta.search_limits = cfg.eval_source.needs_engine().then(|| search_limits(cfg));
+    meta.config_hash = cfg.config_hash();
+    let embedded: Vec<Option<EmbeddedEval>> = parsed.plies.iter().map(|p| p.eval_after).collect();
+    let (summaries, last_after_summary) =
+        analyze_engine_summaries(worker, store, rules, &plies, &embedded, cfg).await?;
+    let alternatives = analyze_alternatives(worker, &plies, &summaries, cfg).await?;
+    // Read after the searches, so a restarted engine's options are the ones recorded.
+    meta.engine = worker.engine.as_ref().map(|e| e.info().clone());
+    meta.engine_restarts = worker.restarts;
+    let clock_states = derive_clock_states(&plies, time_control, &clock_diagnostics);
     let analyses = build_ply_analyses(
         plies,
         &summaries,
+        alternatives,
         last_after_summary.as_ref(),
         &clock_states,
+        armageddon,
         cfg,
     )?;
-    let summary = build_summary(&analyses, &cfg.label_config);
+    let summary = build_summary(&analyses, &cfg.label_config, &clock_diagnostics);
 
     Ok(GameAnalysis {
         meta,
         plies: analyses,
         summary,
+        clock_diagnostics,
     })
 }
 
+struct ResolvedTimeControl {
+    time_control: Option<crate::model::TimeControl>,
+    source: Option<TimeControlSource>,
+    confidence: Option<f32>,
+    /// Why an inferred time control was not used.
+    warning: Option<String>,
+}
+
+/// Header, fallback or inferred time control, with the delay and per-colour starting clocks
+/// applied. An inference below `MIN_INFERRED_CONFIDENCE` is dropped with a warning.
+fn resolve_time_control(
+    headers: &HashMap<String, String>,
+    plies: &[crate::model::PlyRecord],
+    cfg: &AnalysisConfig,
+) -> ResolvedTimeControl {
+    let mut resolved = if let Some(tc) = parse_time_control_header(headers) {
+        ResolvedTimeControl {
+            time_control: Some(tc),
+            source: Some(TimeControlSource::Header),
+            confidence: None,
+            warning: None,
+        }
+    } else if let Some(tc) = cfg.fallback_time_control.clone() {
+        ResolvedTimeControl {
+            time_control: Some(tc),
+            source: Some(TimeControlSource::Fallback),
+            confidence: None,
+            warning: None,
+        }
+    } else {
+        match infer_time_control(plies) {
+            Some(inferred) if inferred.confidence >= MIN_INFERRED_CONFIDENCE => {
+                ResolvedTimeControl {
+                    time_control: Some(inferred.time_control),
+                    source: Some(TimeControlSource::Inferred),
+                    confidence: Some(inferred.confidence),
+                    warning: None,
+                }
+            }
+            weak => ResolvedTimeControl {
+                time_control: None,
+                source: None,
+                confidence: None,
+                warning: weak.map(|inferred| {
+                    format!(
+                        "The time control inferred from the clocks ({}+{}) was not used: its \
+                         confidence {:.2} is below {:.2}.",
+                        inferred.time_control.base_secs,
+                        inferred.time_control.increment_secs,
+                        inferred.confidence,
+                        MIN_INFERRED_CONFIDENCE
+                    )
+                }),
+            },
+        }
+    };
+    apply_starting_clocks(&mut resolved, headers, cfg);
+    // After the starting clocks, which can create a time control where there was none.
+    if let (Some(tc), Some(delay)) = (resolved.time_control.as_mut(), cfg.delay) {
+        tc.delay = Some(delay);
+    }
+    resolved
+}
+
+/// Per-colour starting clocks from the config or the `WhiteClock`/`BlackClock` headers.
+fn apply_starting_clocks(
+    resolved: &mut ResolvedTimeControl,
+    headers: &HashMap<String, String>,
+    cfg: &AnalysisConfig,
+) {
+    let (header_white, header_black) = parse_starting_clocks(headers);
+    let white = cfg.white_clock_secs.or(header_white);
+    let black = cfg.black_clock_secs.or(header_black);
+    if white.is_none() && black.is_none() {
+        return;
+    }
+
+    let mut tc = resolved.time_control.take().unwrap_or_else(|| {
+        crate::model::TimeControl::sudden_death(white.or(black).unwrap_or(0), 0)
+    });
+    let old_white = tc.base_secs;
+    let old_black = tc.base_secs_for(Color::Black);
+    tc.base_secs = white.unwrap_or(old_white);
+    let black = black.unwrap_or(old_black);
+    tc.black_base_secs = (black != tc.base_secs).then_some(black);
+    resolved.time_control = Some(tc);
+    if resolved.source.is_none() {
+        let from_config = cfg.white_clock_secs.is_some() || cfg.black_clock_secs.is_some();
+        resolved.source = Some(if from_config {
+            TimeControlSource::Fallback
+        } else {
+            TimeControlSource::Header
+        });
+    }
+}
+
 fn build_meta(
     parsed: &crate::pgn::ParsedGame,
-    time_control: Option<crate::model::TimeControl>,
+    resolved: ResolvedTimeControl,
+    armageddon: bool,
     platform: crate::model::SourcePlatform,
 ) -> GameMeta {
     GameMeta {
@@ -149,75 +962,172 @@ fn build_meta(
         white: parsed.headers.get("White").cloned(),
         black: parsed.headers.get("Black").cloned(),
         result: parsed.headers.get("Result").cloned(),
-        time_control,
+        time_control: resolved.time_control,
+        time_control_source: resolved.source,
+        time_control_confidence: resolved.confidence,
+        armageddon,
+        search_limits: None,
+        engine: None,
+        config_hash: String::new(),
+        engine_restarts: 0,
         platform,
         headers: parsed.headers.clone(),
+        warnings: Vec::new(),
     }
 }
 
 async fn analyze_engine_summaries(
-    engine: &mut UciEngine,
+    worker: &mut EngineWorker,
+    store: Option<&EvalStore>,
+    rules: GameRules,
     plies: &[crate::model::PlyRecord],
+    embedded: &[Option<EmbeddedEval>],
     cfg: &AnalysisConfig,
 ) -> Result<(Vec<EngineSummary>, Option<EngineSummary>)> {
-    let mut cache: HashMap<String, EngineSummary> = HashMap::new();
     let mut summaries: Vec<EngineSummary> = Vec::with_capacity(plies.len());
 
-    for ply in plies.iter() {
-        let mut summary = analyze_position(
-            engine,
-            &mut cache,
-            &ply.fen_before,
-            cfg.depth,
-            cfg.movetime_ms,
-            cfg.multipv,
-        )
-        .await?;
+    for (idx, ply) in plies.iter().enumerate() {
+        if let Some(summary) = embedded_summary_for_index(idx, embedded, ply.mover, cfg) {
+            summaries.push(summary);
+            continue;
+        }
+
+        require_engine(worker, ply.ply_index)?;
+        let mut summary =
+            analyze_position(worker, store, rules, &ply.fen_before, search_limits(cfg)).await?;
 
         normalize_summary_for_white(&mut summary, ply.mover);
-        summary.played_cp_white = played_cp_for_ply(engine, ply, cfg, &summary).await?;
+        let played = played_score_for_ply(worker, ply, cfg, &summary).await?;
+        summary.played_score_white = played;
+        summary.played_cp_white = played.map(Score::cp);
 
         fill_engine_metrics(&mut summary, ply.mover);
         summaries.push(summary);
     }
 
-    let last_after_summary = analyze_last_after_summary(engine, plies, cfg, &mut cache).await?;
+    let last_after_summary = match embedded_last_after_summary(plies, embedded, cfg) {
+        Some(summary) => Some(summary),
+        None if cfg.eval_source.needs_engine() => {
+            require_engine(worker, plies.len() as u32)?;
+            analyze_last_after_summary(worker, store, rules, plies, cfg).await?
+        }
+        None => None,
+    };
     Ok((summaries, last_after_summary))
 }
 
-async fn played_cp_for_ply(
-    engine: &mut UciEngine,
+fn require_engine(worker: &EngineWorker, ply_index: u32) -> Result<()> {
+    if worker.has_engine() {
+        Ok(())
+    } else {
+        Err(anyhow!("No engine available to analyse ply {}", ply_index))
+    }
+}
+
+fn embedded_summary_for_index(
+    idx: usize,
+    embedded: &[Option<EmbeddedEval>],
+    mover: Color,
+    cfg: &AnalysisConfig,
+) -> Option<EngineSummary> {
+    let before = idx
+        .checked_sub(1)
+        .and_then(|prev| embedded.get(prev).copied().flatten());
+    let after = embedded.get(idx).copied().flatten();
+
+    match cfg.eval_source {
+        EvalSource::Engine => None,
+        EvalSource::Embedded => Some(summary_from_embedded(before, after, mover)),
+        EvalSource::Mixed => match (before, after) {
+            (Some(_), Some(_)) => Some(summary_from_embedded(before, after, mover)),
+            _ => None,
+        },
+    }
+}
+
+fn embedded_last_after_summary(
+    plies: &[crate::model::PlyRecord],
+    embedded: &[Option<EmbeddedEval>],
+    cfg: &AnalysisConfig,
+) -> Option<EngineSummary> {
+    if cfg.eval_source == EvalSource::Engine || plies.is_empty() {
+        return None;
+    }
+    let last = embedded.get(plies.len() - 1).copied().flatten()?;
+    let side_to_move = match plies[plies.len() - 1].mover {
+        Color::White => Color::Black,
+        Color::Black => Color::White,
+    };
+    Some(summary_from_embedded(Some(last), None, side_to_move))
+}
+
+async fn played_score_for_ply(
+    worker: &mut EngineWorker,
     ply: &crate::model::PlyRecord,
     cfg: &AnalysisConfig,
     summary: &EngineSummary,
-) -> Result<Option<i32>> {
-    let played_cp = summary
+) -> Result<Option<Score>> {
+    let played = summary
         .lines
         .iter()
         .find(|l| l.uci == ply.uci)
-        .map(|l| l.cp_white);
+        .map(EngineLine::score);
 
-    if let Some(cp) = played_cp {
-        return Ok(Some(cp));
+    if let Some(score) = played {
+        return Ok(Some(score));
     }
 
-    let mut search_summary = analyze_position_searchmove(
-        engine,
-        &ply.fen_before,
-        &ply.uci,
-        cfg.depth,
-        cfg.movetime_ms,
-    )
-    .await?;
+    let mut search_summary =
+        analyze_position_searchmove(worker, &ply.fen_before, &ply.uci, search_limits(cfg)).await?;
     normalize_summary_for_white(&mut search_summary, ply.mover);
-    Ok(search_summary.lines.first().map(|l| l.cp_white))
+    Ok(search_summary.lines.first().map(EngineLine::score))
+}
+
+async fn analyze_alternatives(
+    worker: &mut EngineWorker,
+    plies: &[crate::model::PlyRecord],
+    summaries: &[EngineSummary],
+    cfg: &AnalysisConfig,
+) -> Result<Vec<Vec<AlternativeMove>>> {
+    let mut out = Vec::with_capacity(plies.len());
+
+    for (ply, summary) in plies.iter().zip(summaries) {
+        let mut alternatives = Vec::new();
+        if cfg.analyze_variations {
+            for line in ply.variations.iter() {
+                let Some(first) = line.first() else {
+                    continue;
+                };
+                let score = if worker.has_engine() {
+                    played_score_for_ply(worker, first, cfg, summary).await?
+                } else {
+                    None
+                };
+                let gain_cp_mover = score
+                    .zip(summary.played_score_white)
+                    .map(|(alt, played)| score_gap(alt, played, ply.mover));
+                alternatives.push(AlternativeMove {
+                    san: first.san.clone(),
+                    uci: first.uci.clone(),
+                    line_san: line.iter().map(|p| p.san.clone()).collect(),
+                    comment: first.comment.clone(),
+                    cp_white: score.map(Score::cp),
+                    gain_cp_mover,
+                });
+            }
+        }
+        out.push(alternatives);
+    }
+
+    Ok(out)
 }
 
 async fn analyze_last_after_summary(
-    engine: &mut UciEngine,
+    worker: &mut EngineWorker,
+    store: Option<&EvalStore>,
+    rules: GameRules,
     plies: &[crate::model::PlyRecord],
     cfg: &AnalysisConfig,
-    cache: &mut HashMap<String, EngineSummary>,
 ) -> Result<Option<EngineSummary>> {
     let last_ply = match plies.last() {
         Some(ply) => ply,
@@ -227,23 +1137,16 @@ async fn analyze_last_after_summary(
         return Ok(None);
     }
 
-    let summary = analyze_position(
-        engine,
-        cache,
-        &last_ply.fen_after,
-        cfg.depth,
-        cfg.movetime_ms,
-        cfg.multipv,
-    )
-    .await?;
-    let mut summary = summary;
+    let limits = search_limits(cfg);
+    let mut summary = analyze_position(worker, store, rules, &last_ply.fen_after, limits).await?;
     let side_to_move = match last_ply.mover {
         Color::White => Color::Black,
         Color::Black => Color::White,
     };
     normalize_summary_for_white(&mut summary, side_to_move);
-    if let Some(best) = summary.lines.first().map(|l| l.cp_white) {
-        summary.best_cp_white = Some(best);
+    if let Some(best) = summary.lines.first() {
+        summary.best_cp_white = Some(best.cp_white);
+        summary.best_score_white = Some(best.score());
     }
     Ok(Some(summary))
 }
@@ -251,11 +1154,34 @@ async fn analyze_last_after_summary(
 fn build_ply_analyses(
     plies: Vec<crate::model::PlyRecord>,
     summaries: &[EngineSummary],
+    alternatives: Vec<Vec<AlternativeMove>>,
     last_after_summary: Option<&EngineSummary>,
     clock_states: &[ClockState],
+    armageddon: bool,
     cfg: &AnalysisConfig,
 ) -> Result<Vec<PlyAnalysis>> {
     let mut analyses: Vec<PlyAnalysis> = Vec::with_capacity(plies.len());
+    let mut alternatives = alternatives.into_iter();
+    // White's winning chance from the engine's WDL, shifted by `shift_cp` of time equity, or
+    // from the sigmoid of `cp + shift_cp`.
+    let white_prob = |wdl: Option<Wdl>, cp: i32, shift_cp: i32| match wdl {
+        Some(wdl) => {
+            let p = if armageddon {
+                wdl.win_prob()
+            } else {
+                wdl.expected_score()
+            };
+            shift_prob_by_cp(cfg.k_sigmoid, p, shift_cp)
+        }
+        None => {
+            let p = win_prob_from_cp(cfg.k_sigmoid, cp + shift_cp);
+            if armageddon {
+                armageddon_white_prob(p)
+            } else {
+                p
+            }
+        }
+    };
 
     for (idx, ply) in plies.into_iter().enumerate() {
         let summary = summaries
@@ -264,21 +1190,30 @@ fn build_ply_analyses(
             .ok_or_else(|| anyhow!("Missing engine summary for ply {}", idx + 1))?;
 
         let cp_eval_before = summary.best_cp_white.unwrap_or(0);
-        let cp_eval_after = cp_eval_after_for_index(
-            idx,
-            summaries,
-            last_after_summary,
-            cp_eval_before,
-        );
+        let cp_eval_after =
+            cp_eval_after_for_index(idx, summaries, last_after_summary, cp_eval_before);
         let (tau_before, tau_after) = time_equity_for_ply(clock_states.get(idx), cfg, &ply);
 
         let cp_practical_before = cp_eval_before + tau_before;
         let cp_practical_after = cp_eval_after + tau_after;
 
-        let p_eval_before = win_prob_from_cp(cfg.k_sigmoid, cp_eval_before);
-        let p_eval_after = win_prob_from_cp(cfg.k_sigmoid, cp_eval_after);
-        let p_practical_before = win_prob_from_cp(cfg.k_sigmoid, cp_practical_before);
-        let p_practical_after = win_prob_from_cp(cfg.k_sigmoid, cp_practical_after);
+        // Both sides of the move need WDL; mixing it with the sigmoid would distort the deltas.
+        let (wdl_before, wdl_after) = summary
+            .lines
+            .first()
+            .and_then(|l| l.wdl)
+            .zip(
+                summary_after_index(idx, summaries, last_after_summary)
+                    .and_then(|s| s.lines.first())
+                    .and_then(|l| l.wdl),
+            )
+            .filter(|_| cfg.use_wdl)
+            .unzip();
+
+        let p_eval_before = white_prob(wdl_before, cp_eval_before, 0);
+        let p_eval_after = white_prob(wdl_after, cp_eval_after, 0);
+        let p_practical_before = white_prob(wdl_before, cp_eval_before, tau_before);
+        let p_practical_after = white_prob(wdl_after, cp_eval_after, tau_after);
 
         let dp_eval_mover =
             mover_prob(p_eval_after, ply.mover) - mover_prob(p_eval_before, ply.mover);
@@ -299,26 +1234,63 @@ fn build_ply_analyses(
             dp_practical_mover,
         };
 
-        let label = label_move(
+        let suspect = cfg.label_config.exclude_suspect_clocks
+            && clock_states.get(idx).map(|c| c.suspect).unwrap_or(false);
+        let (think_time, clock_before) = if suspect {
+            (None, None)
+        } else {
+            (ply.think_time_secs, ply.clock_before_secs)
+        };
+        let mut label = label_move(
             &cfg.label_config,
-            ply.think_time_secs,
-            ply.clock_before_secs,
+            think_time,
+            clock_before,
+            ply.premove && !suspect,
             summary.punish_cp_mover,
             summary.complexity_cp_mover,
             dp_practical_mover,
         );
+        if let (Some(best), Some(played)) = (summary.best_score_white, summary.played_score_white) {
+            let mating_line = summary.lines.first().map(|l| l.pv_san.as_slice());
+            note_missed_mate(
+                &mut label,
+                best.for_mover(ply.mover),
+                played.for_mover(ply.mover),
+                ply.ply_index,
+                mating_line.unwrap_or_default(),
+            );
+        }
+        if let Some(reply) =
+            summary_after_index(idx, summaries, last_after_summary).and_then(|s| s.lines.first())
+        {
+            quote_refutation(&mut label, ply.ply_index + 1, &reply.pv_san);
+        }
 
         analyses.push(PlyAnalysis {
             ply,
             engine_before: summary,
             metrics,
             label,
+            alternatives: alternatives.next().unwrap_or_default(),
         });
     }
 
     Ok(analyses)
 }
 
+/// The search of the position after ply `idx`, i.e. the opponent's best replies.
+fn summary_after_index<'a>(
+    idx: usize,
+    summaries: &'a [EngineSummary],
+    last_after_summary: Option<&'a EngineSummary>,
+) -> Option<&'a EngineSummary> {
+    match summaries.get(idx + 1) {
+        Some(summary) => Some(summary),
+        None if idx + 1 == summaries.len() => last_after_summary,
+        None => None,
+    }
+}
+
 fn cp_eval_after_for_index(
     idx: usize,
     summaries: &[EngineSummary],
@@ -353,6 +1325,7 @@ fn time_equity_for_ply(
                 cfg.time_pressure_boost,
                 w,
                 b,
+                c.delay_secs,
                 ply.ply_index,
             )),
             _ => None,
@@ -368,6 +1341,7 @@ fn time_equity_for_ply(
                 cfg.time_pressure_boost,
                 w,
                 b,
+                c.delay_secs,
                 ply.ply_index,
             )),
             _ => None,
@@ -376,38 +1350,61 @@ fn time_equity_for_ply(
     (tau_before, tau_after)
 }
 
+/// The search mode actually used: `nodes`, else `movetime_ms`, else `depth`.
+fn search_limits(cfg: &AnalysisConfig) -> SearchLimits {
+    let (depth, movetime_ms, nodes) = match (cfg.nodes, cfg.movetime_ms) {
+        (Some(nodes), _) => (None, None, Some(nodes)),
+        (None, Some(ms)) => (None, Some(ms), None),
+        (None, None) => (Some(cfg.depth), None, None),
+    };
+    SearchLimits {
+        depth,
+        movetime_ms,
+        nodes,
+        multipv: cfg.multipv,
+    }
+}
+
 async fn analyze_position(
-    engine: &mut UciEngine,
-    cache: &mut HashMap<String, EngineSummary>,
+    worker: &mut EngineWorker,
+    store: Option<&EvalStore>,
+    rules: GameRules,
     fen: &str,
-    depth: u16,
-    movetime_ms: Option<u64>,
-    multipv: u8,
+    limits: SearchLimits,
 ) -> Result<EngineSummary> {
-    if let Some(summary) = cache.get(fen) {
-        return Ok(summary.clone());
-    }
+    let key = store.map(|s| s.key(fen)).transpose()?;
+    let cached = store
+        .zip(key.as_deref())
+        .and_then(|(store, key)| store.get(key, &limits));
+    let mut summary = match cached {
+        Some(summary) => summary,
+        None => {
+            let summary = worker.search(fen, &limits, None).await?;
+            if let Some((store, key)) = store.zip(key) {
+                store.insert(key, limits, summary.clone());
+            }
+            summary
+        }
+    };
 
-    engine.position_fen(fen).await?;
-    let summary = engine
-        .go_multipv(depth, movetime_ms, multipv, None)
-        .await?;
-    cache.insert(fen.to_string(), summary.clone());
+    // SAN is derived on every run rather than cached; it is cheap and follows the game's rules.
+    for line in summary.lines.iter_mut() {
+        line.pv_san = pv_to_san(fen, rules.variant, rules.castling_mode(), &line.pv_uci)?;
+    }
     Ok(summary)
 }
 
 async fn analyze_position_searchmove(
-    engine: &mut UciEngine,
+    worker: &mut EngineWorker,
     fen: &str,
     move_uci: &str,
-    depth: u16,
-    movetime_ms: Option<u64>,
+    limits: SearchLimits,
 ) -> Result<EngineSummary> {
-    engine.position_fen(fen).await?;
-    let summary = engine
-        .go_multipv(depth, movetime_ms, 1, Some(move_uci))
-        .await?;
-    Ok(summary)
+    let limits = SearchLimits {
+        multipv: 1,
+        ..limits
+    };
+    worker.search(fen, &limits, Some(move_uci)).await
 }
 
 #[derive(Clone, Debug)]
@@ -416,15 +1413,18 @@ struct ClockState {
     before_black: Option<f32>,
     after_white: Option<f32>,
     after_black: Option<f32>,
+    delay_secs: f32,
+    suspect: bool,
 }
 
 fn derive_clock_states(
     plies: &[crate::model::PlyRecord],
     tc: Option<crate::model::TimeControl>,
+    diagnostics: &ClockDiagnostics,
 ) -> Vec<ClockState> {
     let mut out = Vec::with_capacity(plies.len());
-    let mut last_white = tc.as_ref().map(|t| t.base_secs as f32);
-    let mut last_black = tc.as_ref().map(|t| t.base_secs as f32);
+    let mut last_white = tc.as_ref().map(|t| t.base_secs_for(Color::White) as f32);
+    let mut last_black = tc.as_ref().map(|t| t.base_secs_for(Color::Black) as f32);
 
     for ply in plies.iter() {
         let mut before_white = last_white;
@@ -460,18 +1460,52 @@ fn derive_clock_states(
             }
         }
 
+        let ((white_before, black_before), (white_after, black_after)) = completed_moves(ply);
         out.push(ClockState {
-            before_white,
-            before_black,
-            after_white,
-            after_black,
+            before_white: align_to_stage(before_white, white_before, black_before, tc.as_ref()),
+            before_black: align_to_stage(before_black, black_before, white_before, tc.as_ref()),
+            after_white: align_to_stage(after_white, white_after, black_after, tc.as_ref()),
+            after_black: align_to_stage(after_black, black_after, white_after, tc.as_ref()),
+            delay_secs: tc.as_ref().map(|t| t.delay_secs() as f32).unwrap_or(0.0),
+            suspect: diagnostics.is_suspect(ply.ply_index),
         });
     }
 
     out
 }
 
-fn build_summary(analyses: &[PlyAnalysis], label_cfg: &LabelConfig) -> GameSummary {
+/// Moves completed by (White, Black) before and after `ply`.
+fn completed_moves(ply: &crate::model::PlyRecord) -> ((u32, u32), (u32, u32)) {
+    let m = move_number_for_ply(ply.ply_index);
+    match ply.mover {
+        Color::White => ((m - 1, m - 1), (m, m - 1)),
+        Color::Black => ((m, m - 1), (m, m)),
+    }
+}
+
+/// Adds period bonuses the opponent has already been credited but this player has not, so both
+/// clocks are compared within the same stage (e.g. right around move 40).
+fn align_to_stage(
+    t: Option<f32>,
+    own_completed: u32,
+    other_completed: u32,
+    tc: Option<&crate::model::TimeControl>,
+) -> Option<f32> {
+    let (t, tc) = match (t, tc) {
+        (Some(t), Some(tc)) => (t, tc),
+        (t, _) => return t,
+    };
+    let pending: u32 = (own_completed + 1..=other_completed)
+        .map(|m| tc.bonus_after_move(m))
+        .sum();
+    Some(t + pending as f32)
+}
+
+fn build_summary(
+    analyses: &[PlyAnalysis],
+    label_cfg: &LabelConfig,
+    diagnostics: &ClockDiagnostics,
+) -> GameSummary {
     let mut label_counts: HashMap<String, u32> = HashMap::new();
     let mut think_times: Vec<f32> = Vec::new();
     let mut punish: Vec<i32> = Vec::new();
@@ -483,14 +1517,23 @@ fn build_summary(analyses: &[PlyAnalysis], label_cfg: &LabelConfig) -> GameSumma
     let mut blunders_in_time_trouble = 0u32;
     let mut known_clock_moves = 0u32;
 
-    let mut phase_stats = [PhaseStats::default(), PhaseStats::default(), PhaseStats::default()];
+    let mut phase_stats = [
+        PhaseStats::default(),
+        PhaseStats::default(),
+        PhaseStats::default(),
+    ];
     let mut total_think = 0.0f32;
 
     for ply in analyses {
         let key = format!("{:?}", ply.label.kind);
         *label_counts.entry(key).or_insert(0) += 1;
 
-        if let Some(t) = ply.ply.think_time_secs {
+        let clocks_usable =
+            !(label_cfg.exclude_suspect_clocks && diagnostics.is_suspect(ply.ply.ply_index));
+        let think_time = ply.ply.think_time_secs.filter(|_| clocks_usable);
+        let clock_before = ply.ply.clock_before_secs.filter(|_| clocks_usable);
+
+        if let Some(t) = think_time {
             think_times.push(t);
             total_think += t;
         }
@@ -502,7 +1545,7 @@ fn build_summary(analyses: &[PlyAnalysis], label_cfg: &LabelConfig) -> GameSumma
         }
         dp_practical.push(ply.metrics.dp_practical_mover);
 
-        if let Some(t_rem) = ply.ply.clock_before_secs {
+        if let Some(t_rem) = clock_before {
             known_clock_moves += 1;
             if t_rem <= label_cfg.time_trouble_secs {
                 time_trouble_moves += 1;
@@ -518,7 +1561,7 @@ fn build_summary(analyses: &[PlyAnalysis], label_cfg: &LabelConfig) -> GameSumma
 
         let phase_idx = phase_index(ply.ply.ply_index);
         phase_stats[phase_idx].ply_count += 1;
-        if let Some(t) = ply.ply.think_time_secs {
+        if let Some(t) = think_time {
             phase_stats[phase_idx].think_sum += t;
             phase_stats[phase_idx].think_count += 1;
         }
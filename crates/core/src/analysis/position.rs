@@ -1,18 +1,99 @@
 use anyhow::{anyhow, Result};
 use shakmaty::{
     fen::Fen,
-    san::San,
-    CastlingMode, Chess, EnPassantMode, Position,
+    san::{San, SanPlus},
+    uci::UciMove,
+    variant::{Variant, VariantPosition},
+    zobrist::{Zobrist64, ZobristHash},
+    CastlingMode, EnPassantMode, Position,
 };
+use std::collections::HashMap;
 
 use crate::model::{Color, PlyRecord};
-use crate::pgn::ParsedGame;
+use crate::pgn::{detect_chess960, detect_variant, ParsedGame, RawPly};
 
 pub fn build_ply_records_with_fens(game: &ParsedGame) -> Result<Vec<PlyRecord>> {
-    let mut pos = Chess::default();
-    let mut out: Vec<PlyRecord> = Vec::with_capacity(game.plies.len());
+    build_ply_records_and_warnings(game).map(|(plies, _)| plies)
+}
+
+/// Like `build_ply_records_with_fens`, also returning a warning for every sideline cut short at a
+/// move that cannot be played. Only an unplayable mainline move fails the game.
+pub fn build_ply_records_and_warnings(game: &ParsedGame) -> Result<(Vec<PlyRecord>, Vec<String>)> {
+    let mode = castling_mode(&game.headers);
+    let variant = detect_variant(&game.headers).ok_or_else(|| {
+        anyhow!(
+            "Unsupported variant '{}'",
+            game.headers
+                .get("Variant")
+                .map(String::as_str)
+                .unwrap_or("")
+        )
+    })?;
+    let start = starting_position(&game.headers, variant, mode)?;
+    let first_ply_index = first_ply_index(&start);
+    let mut warnings = Vec::new();
+    let (plies, failure) = build_line(start, &game.plies, first_ply_index, mode, &mut warnings);
+    match failure {
+        Some(err) => Err(err),
+        None => Ok((plies, warnings)),
+    }
+}
+
+pub fn castling_mode(headers: &HashMap<String, String>) -> CastlingMode {
+    if detect_chess960(headers) {
+        CastlingMode::Chess960
+    } else {
+        CastlingMode::Standard
+    }
+}
+
+/// Honours the `SetUp`/`FEN` headers used by set-position, handicap and "from position" games.
+pub fn starting_position(
+    headers: &HashMap<String, String>,
+    variant: Variant,
+    mode: CastlingMode,
+) -> Result<VariantPosition> {
+    let setup_disabled = headers
+        .get("SetUp")
+        .map(|v| v.trim() == "0")
+        .unwrap_or(false);
+    let fen = match headers.get("FEN") {
+        Some(fen) if !setup_disabled => fen,
+        _ => return Ok(VariantPosition::new(variant)),
+    };
+
+    let parsed: Fen = fen
+        .trim()
+        .parse()
+        .map_err(|e| anyhow!("Invalid FEN header '{}': {}", fen, e))?;
+    VariantPosition::from_setup(variant, parsed.into_setup(), mode)
+        .map_err(|e| anyhow!("Illegal FEN header '{}': {}", fen, e))
+}
+
+/// Ply index of the first move, so a game starting at `20. ...` continues the numbering of a
+/// full game and phase detection sees the real game stage.
+fn first_ply_index<P: Position>(pos: &P) -> u32 {
+    let fullmoves = pos.fullmoves().get();
+    let black_to_move = pos.turn() == shakmaty::Color::Black;
+    (fullmoves - 1) * 2 + black_to_move as u32 + 1
+}
+
+/// Replays one line of the move tree. Sidelines are built recursively from the position before
+/// the ply they replace and share its `ply_index`. The line stops at the first move that cannot be
+/// played, which is returned alongside the plies before it; a sideline stopped that way is kept up
+/// to that move and reported in `warnings`.
+fn build_line(
+    start: VariantPosition,
+    raw_plies: &[RawPly],
+    first_ply_index: u32,
+    mode: CastlingMode,
+    warnings: &mut Vec<String>,
+) -> (Vec<PlyRecord>, Option<anyhow::Error>) {
+    let mut pos = start;
+    let mut out: Vec<PlyRecord> = Vec::with_capacity(raw_plies.len());
 
-    for (idx, raw) in game.plies.iter().enumerate() {
+    for (offset, raw) in raw_plies.iter().enumerate() {
+        let ply_index = first_ply_index + offset as u32;
         let mover = match pos.turn() {
             shakmaty::Color::White => Color::White,
             shakmaty::Color::Black => Color::Black,
@@ -20,30 +101,32 @@ pub fn build_ply_records_with_fens(game: &ParsedGame) -> Result<Vec<PlyRecord>>
 
         let fen_before = fen_string(&pos);
 
-        let san: San = raw
-            .san
-            .parse()
-            .map_err(|_| anyhow!("Failed to parse SAN '{}' at ply {}", raw.san, idx + 1))?;
+        let mut variations = Vec::with_capacity(raw.variations.len());
+        for line in raw.variations.iter() {
+            let (plies, failure) = build_line(pos.clone(), line, ply_index, mode, warnings);
+            if let Some(err) = failure {
+                warnings.push(format!(
+                    "Sideline at ply {} cut short after {} move(s): {}",
+                    ply_index,
+                    plies.len(),
+                    err
+                ));
+            }
+            if !plies.is_empty() {
+                variations.push(plies);
+            }
+        }
 
-        let mv = san.to_move(&pos).map_err(|e| {
-            anyhow!(
-                "Illegal/ambiguous SAN '{}' at ply {}: {}",
-                raw.san,
-                idx + 1,
-                e
-            )
-        })?;
-
-        let uci = mv.to_uci(CastlingMode::Standard).to_string();
-
-        let pos_after = pos
-            .play(&mv)
-            .map_err(|e| anyhow!("Failed to apply move at ply {}: {}", idx + 1, e))?;
+        let (mv, pos_after) = match play_san(&pos, &raw.san, ply_index) {
+            Ok(played) => played,
+            Err(err) => return (out, Some(err)),
+        };
+        let uci = mv.to_uci(mode).to_string();
 
         let fen_after = fen_string(&pos_after);
 
         out.push(PlyRecord {
-            ply_index: (idx + 1) as u32,
+            ply_index,
             san: raw.san.clone(),
             uci,
             mover,
@@ -52,14 +135,83 @@ pub fn build_ply_records_with_fens(game: &ParsedGame) -> Result<Vec<PlyRecord>>
             clock_after_secs: raw.clock_after_secs,
             clock_before_secs: None,
             think_time_secs: None,
+            think_time_estimated: false,
+            premove: false,
+            premove_estimated: false,
+            elapsed_move_secs: raw.elapsed_move_secs,
+            comment: raw.comment.clone(),
+            variations,
         });
 
         pos = pos_after;
     }
 
+    (out, None)
+}
+
+fn play_san(
+    pos: &VariantPosition,
+    san: &str,
+    ply_index: u32,
+) -> Result<(shakmaty::Move, VariantPosition)> {
+    let parsed: San = san
+        .parse()
+        .map_err(|_| anyhow!("Failed to parse SAN '{}' at ply {}", san, ply_index))?;
+    let mv = parsed.to_move(pos).map_err(|e| {
+        anyhow!(
+            "Illegal/ambiguous SAN '{}' at ply {}: {}",
+            san,
+            ply_index,
+            e
+        )
+    })?;
+    let pos_after = pos
+        .clone()
+        .play(&mv)
+        .map_err(|e| anyhow!("Failed to apply move at ply {}: {}", ply_index, e))?;
+    Ok((mv, pos_after))
+}
+
+/// Transposition key for cached evaluations: a Zobrist hash of the placement, side to move,
+/// castling rights and a capturable en-passant square. The move counters are ignored, so
+/// repetitions and transpositions share one key.
+pub fn position_key(fen: &str, variant: Variant, mode: CastlingMode) -> Result<String> {
+    let pos = position_from_fen(fen, variant, mode)?;
+    let hash: Zobrist64 = pos.zobrist_hash(EnPassantMode::Legal);
+    Ok(format!("{:016x}", hash.0))
+}
+
+/// Converts an engine PV to SAN (with check marks). Conversion stops at the first move that is
+/// malformed or illegal, so a truncated or garbled PV still yields its playable prefix.
+pub fn pv_to_san(
+    fen: &str,
+    variant: Variant,
+    mode: CastlingMode,
+    pv: &[String],
+) -> Result<Vec<String>> {
+    let mut pos = position_from_fen(fen, variant, mode)?;
+    let mut out = Vec::with_capacity(pv.len());
+    for uci in pv {
+        let Some(mv) = uci
+            .parse::<UciMove>()
+            .ok()
+            .and_then(|uci| uci.to_move(&pos).ok())
+        else {
+            break;
+        };
+        out.push(SanPlus::from_move_and_play_unchecked(&mut pos, &mv).to_string());
+    }
     Ok(out)
 }
 
+fn position_from_fen(fen: &str, variant: Variant, mode: CastlingMode) -> Result<VariantPosition> {
+    let parsed: Fen = fen
+        .parse()
+        .map_err(|e| anyhow!("Invalid FEN '{}': {}", fen, e))?;
+    VariantPosition::from_setup(variant, parsed.into_setup(), mode)
+        .map_err(|e| anyhow!("Illegal FEN '{}': {}", fen, e))
+}
+
 fn fen_string<P: Position + Clone>(pos: &P) -> String {
     Fen::from_position(pos.clone(), EnPassantMode::Legal).to_string()
 }
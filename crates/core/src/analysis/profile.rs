@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::analysis::pipeline::AnalysisConfig;
+
+/// A named engine setup: binary, UCI options and search limits. Unset fields leave the
+/// configuration they are applied to unchanged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EngineProfile {
+    pub path: Option<String>,
+    /// Values may be written as JSON strings, numbers or booleans.
+    #[serde(default, deserialize_with = "option_values")]
+    pub options: BTreeMap<String, String>,
+    pub depth: Option<u16>,
+    pub movetime_ms: Option<u64>,
+    pub nodes: Option<u64>,
+    pub multipv: Option<u8>,
+    pub threads: Option<u32>,
+    pub hash_mb: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfilesFile {
+    profiles: BTreeMap<String, EngineProfile>,
+}
+
+impl EngineProfile {
+    /// Overlays the profile on `cfg`. Profile options are merged into `cfg.engine_options`; a
+    /// profile that sets `nodes` or `movetime_ms` replaces both, so its search mode wins.
+    pub fn apply(&self, cfg: &mut AnalysisConfig) {
+        if let Some(path) = self.path.as_ref() {
+            cfg.engine_path = path.clone();
+        }
+        cfg.engine_options.extend(self.options.clone());
+        if let Some(depth) = self.depth {
+            cfg.depth = depth;
+        }
+        if self.nodes.is_some() || self.movetime_ms.is_some() {
+            cfg.nodes = self.nodes;
+            cfg.movetime_ms = self.movetime_ms;
+        }
+        if let Some(multipv) = self.multipv {
+            cfg.multipv = multipv;
+        }
+        if self.threads.is_some() {
+            cfg.threads = self.threads;
+        }
+        if self.hash_mb.is_some() {
+            cfg.hash_mb = self.hash_mb;
+        }
+    }
+}
+
+/// Reads a profiles file of the form `{ "profiles": { "<name>": { ... } } }`.
+pub fn load_profiles(path: &Path) -> Result<BTreeMap<String, EngineProfile>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read profiles {}: {}", path.display(), e))?;
+    parse_profiles(&text).map_err(|e| anyhow!("Invalid profiles {}: {}", path.display(), e))
+}
+
+pub fn parse_profiles(text: &str) -> Result<BTreeMap<String, EngineProfile>> {
+    let file: ProfilesFile = serde_json::from_str(text)?;
+    Ok(file.profiles)
+}
+
+pub fn load_profile(path: &Path, name: &str) -> Result<EngineProfile> {
+    let mut profiles = load_profiles(path)?;
+    profiles.remove(name).ok_or_else(|| {
+        let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+        anyhow!(
+            "No engine profile '{}' in {} (available: {}).",
+            name,
+            path.display(),
+            if known.is_empty() {
+                "none".to_string()
+            } else {
+                known.join(", ")
+            }
+        )
+    })
+}
+
+fn option_values<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = BTreeMap::<String, serde_json::Value>::deserialize(deserializer)?;
+    raw.into_iter()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(s) => Ok((name, s)),
+            serde_json::Value::Number(n) => Ok((name, n.to_string())),
+            serde_json::Value::Bool(b) => Ok((name, b.to_string())),
+            other => Err(serde::de::Error::custom(format!(
+                "option '{}' must be a string, number or boolean, not {}",
+                name, other
+            ))),
+        })
+        .collect()
+}
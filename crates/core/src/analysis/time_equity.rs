@@ -11,6 +11,11 @@ pub fn phase_multiplier(ply_index: u32) -> f32 {
     }
 }
 
+/// Moves ahead over which a per-move delay is counted as spare time on both clocks.
+pub const DELAY_HORIZON_MOVES: f32 = 10.0;
+
+/// `delay_secs` is the per-move delay window; it is free time for both players, so it widens the
+/// total without changing the difference.
 #[allow(clippy::too_many_arguments)]
 pub fn time_equity_white_cp(
     alpha: f32,
@@ -20,11 +25,14 @@ pub fn time_equity_white_cp(
     pressure_boost: f32,
     t_white: f32,
     t_black: f32,
+    delay_secs: f32,
     ply_index: u32,
 ) -> i32 {
-    let t_total = t_white + t_black;
+    let free = delay_secs.max(0.0) * DELAY_HORIZON_MOVES;
+    let t_total = t_white + t_black + 2.0 * free;
     let v = alpha / (t_total + beta);
-    let pressure = time_pressure_multiplier(t_total, pressure_pivot, pressure_scale, pressure_boost);
+    let pressure =
+        time_pressure_multiplier(t_total, pressure_pivot, pressure_scale, pressure_boost);
     let tau_pawns = v * pressure * (t_white - t_black) * phase_multiplier(ply_index);
     (tau_pawns * 100.0).round() as i32
 }
@@ -33,7 +41,11 @@ fn time_pressure_multiplier(total_secs: f32, pivot: f32, scale: f32, boost: f32)
     if boost <= 0.0 {
         return 1.0;
     }
-    let scale = if scale.abs() < f32::EPSILON { 1.0 } else { scale };
+    let scale = if scale.abs() < f32::EPSILON {
+        1.0
+    } else {
+        scale
+    };
     let z = (total_secs - pivot) / scale;
     let sigmoid = 1.0 / (1.0 + (-z).exp());
     1.0 + boost * (1.0 - sigmoid)
@@ -44,6 +56,27 @@ pub fn win_prob_from_cp(k: f32, cp_white: i32) -> f32 {
     sigmoid(x)
 }
 
+/// Moves a probability by `shift_cp` in the sigmoid's logit space, so a time edge shifts a WDL
+/// expected score the way it would shift a centipawn score.
+pub fn shift_prob_by_cp(k: f32, p: f32, shift_cp: i32) -> f32 {
+    if shift_cp == 0 {
+        return p;
+    }
+    let p = p.clamp(1e-4, 1.0 - 1e-4);
+    let logit = (p / (1.0 - p)).ln();
+    sigmoid(logit + k * (shift_cp as f32 / 100.0))
+}
+
+/// Share of the expected score assumed to come from draws in a level position, used to split the
+/// sigmoid's expected score into wins and draws when draws count for Black.
+pub const ARMAGEDDON_DRAW_SHARE: f32 = 0.5;
+
+/// White's winning chance in an Armageddon game, where a draw scores as a Black win.
+pub fn armageddon_white_prob(p_white: f32) -> f32 {
+    let draw = ARMAGEDDON_DRAW_SHARE * (1.0 - (2.0 * p_white - 1.0).abs());
+    (p_white - draw / 2.0).clamp(0.0, 1.0)
+}
+
 pub fn mover_prob(p_white: f32, mover: Color) -> f32 {
     match mover {
         Color::White => p_white,
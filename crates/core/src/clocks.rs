@@ -1,53 +1,143 @@
-use crate::model::{Color, PlyRecord, SourcePlatform, TimeControl};
+use crate::model::{
+    move_number_for_ply, ClockAnomaly, ClockAnomalyKind, ClockDiagnostics, Color, PlyRecord,
+    SourcePlatform, TimeControl,
+};
+
+/// Slack for rounded clock readings before a deduction counts as inconsistent.
+const CLOCK_TOLERANCE_SECS: f32 = 0.5;
 
 #[derive(Debug, Clone, Copy)]
 pub struct ClockPolicy {
     pub increment_applied_after_move: bool,
+    /// Clock rises up to this much beyond the increment are the server refunding network lag,
+    /// not an anomaly.
+    pub lag_compensation_secs: f32,
+    /// Moves whose deducted time is at most this are flagged as likely premoves; `None` when the
+    /// platform's premoves cannot be told apart from fast moves.
+    pub premove_max_secs: Option<f32>,
+    /// Whole-second readings round fast moves down to 0s, so in such games a 0s move is only
+    /// flagged when it answers an instant opponent move, and then as `premove_estimated`.
+    pub premove_needs_subsecond_clocks: bool,
 }
 
 impl ClockPolicy {
     pub fn for_platform(platform: SourcePlatform) -> Self {
         match platform {
-            SourcePlatform::Lichess | SourcePlatform::ChessCom => Self {
+            // Usually whole-second readings; lag compensation can lift the clock by up to a
+            // second and a premove costs nothing.
+            SourcePlatform::Lichess => Self {
+                increment_applied_after_move: true,
+                lag_compensation_secs: 1.0,
+                premove_max_secs: Some(0.0),
+                premove_needs_subsecond_clocks: true,
+            },
+            // Tenth-second readings; every premove is charged a flat 0.1s.
+            SourcePlatform::ChessCom => Self {
                 increment_applied_after_move: true,
+                lag_compensation_secs: 0.0,
+                premove_max_secs: Some(0.1),
+                premove_needs_subsecond_clocks: false,
             },
             SourcePlatform::Unknown => Self {
                 increment_applied_after_move: true,
+                lag_compensation_secs: 0.0,
+                premove_max_secs: None,
+                premove_needs_subsecond_clocks: false,
             },
         }
     }
+
+    /// Returns whether the move looks premoved and whether that call is only estimated.
+    /// `opponent_spent` is the deduction of the opponent's previous move, if known.
+    ///
+    /// A player's first move is excluded: its clock usually does not run yet. So is a move whose
+    /// clock rose (`raw_spent < 0`): that is a lag refund, which says nothing about a premove.
+    fn premove(
+        self,
+        ply_index: u32,
+        raw_spent: f32,
+        opponent_spent: Option<f32>,
+        subsecond_clocks: bool,
+    ) -> (bool, bool) {
+        let max = match self.premove_max_secs {
+            Some(max) => max,
+            None => return (false, false),
+        };
+        let instant = |spent: f32| (0.0..=max + 0.01).contains(&spent);
+        if move_number_for_ply(ply_index) <= 1 || !instant(raw_spent) {
+            return (false, false);
+        }
+        if !self.premove_needs_subsecond_clocks || subsecond_clocks {
+            return (true, false);
+        }
+        // A lone 0s move is as likely a fast move rounded down; a run of them is a premove
+        // exchange far more often than two players both answering in under a second.
+        let in_run = opponent_spent.map(instant).unwrap_or(false);
+        (in_run, in_run)
+    }
 }
 
+/// Fills clock-before and think times, clamping impossible deductions and reporting them in the
+/// returned diagnostics.
 pub fn derive_clock_before_and_think_times(
     plies: &mut [PlyRecord],
     tc: Option<TimeControl>,
     platform: SourcePlatform,
-) {
-    let inc = tc.as_ref().map(|t| t.increment_secs as f32).unwrap_or(0.0);
-    let base = tc.as_ref().map(|t| t.base_secs as f32);
+) -> ClockDiagnostics {
+    let tc = tc.as_ref();
     let mut policy = ClockPolicy::for_platform(platform);
-    if platform == SourcePlatform::Unknown && inc > 0.0 {
-        policy = infer_policy(plies, base, inc, policy);
+    if platform == SourcePlatform::Unknown && tc.map(|t| t.has_increment()).unwrap_or(false) {
+        policy = infer_policy(plies, tc, policy);
     }
 
-    let mut last_white_after: Option<f32> = None;
-    let mut last_black_after: Option<f32> = None;
+    // Seeded with the starting clocks; a missing reading leaves the next clock-before unknown
+    // instead of falling back to the base.
+    let mut last_white_after = tc.map(|t| t.base_secs_for(Color::White) as f32);
+    let mut last_black_after = tc.map(|t| t.base_secs_for(Color::Black) as f32);
+    let mut anomalies: Vec<ClockAnomaly> = Vec::new();
+    let mut has_readings = false;
+    // Deduction of the previous ply, left unset for a player's first move.
+    let mut last_spent: Option<f32> = None;
+    let subsecond_clocks = plies
+        .iter()
+        .filter_map(|p| p.clock_after_secs)
+        .any(|c| c.fract() != 0.0);
 
     for ply in plies.iter_mut() {
-        let t_before = match ply.mover {
-            Color::White => last_white_after.or(base),
-            Color::Black => last_black_after.or(base),
+        let credit = ClockCredit::for_ply(tc, ply.ply_index);
+        let anomaly = |kind, magnitude_secs| ClockAnomaly {
+            ply_index: ply.ply_index,
+            mover: ply.mover,
+            kind,
+            magnitude_secs,
         };
+        if ply.clock_after_secs.is_none() && ply.elapsed_move_secs.is_none() {
+            anomalies.push(anomaly(ClockAnomalyKind::Missing, 0.0));
+        } else {
+            has_readings = true;
+        }
+        let t_before = credit.reset_secs.or(match ply.mover {
+            Color::White => last_white_after,
+            Color::Black => last_black_after,
+        });
 
         ply.clock_before_secs = t_before;
+        let opponent_spent = last_spent.take();
 
-        if let (Some(t_before), Some(t_after)) = (t_before, ply.clock_after_secs) {
-            let mut spent = if policy.increment_applied_after_move {
-                (t_before + inc) - t_after
-            } else {
-                t_before - t_after
-            };
-            let max_spent = t_before + inc;
+        if let Some(emt) = ply.elapsed_move_secs {
+            if let (Some(t_before), None) = (t_before, ply.clock_after_secs) {
+                ply.clock_after_secs = Some(credit.rebuild_clock_after(t_before, emt, policy));
+            }
+            ply.think_time_secs = Some(emt);
+        } else if let (Some(t_before), Some(t_after)) = (t_before, ply.clock_after_secs) {
+            let raw_spent = credit.spent(t_before, t_after, policy.increment_applied_after_move);
+            let mut spent = raw_spent;
+            let max_spent = t_before + credit.increment + credit.delay;
+            if spent < -(CLOCK_TOLERANCE_SECS + policy.lag_compensation_secs) {
+                anomalies.push(anomaly(ClockAnomalyKind::UnexpectedGain, -spent));
+            } else if spent > max_spent + CLOCK_TOLERANCE_SECS {
+                anomalies.push(anomaly(ClockAnomalyKind::Overspent, spent - max_spent));
+            }
             if spent < 0.0 {
                 spent = 0.0;
             }
@@ -55,6 +145,11 @@ pub fn derive_clock_before_and_think_times(
                 spent = max_spent;
             }
             ply.think_time_secs = Some(spent);
+            (ply.premove, ply.premove_estimated) =
+                policy.premove(ply.ply_index, raw_spent, opponent_spent, subsecond_clocks);
+            if move_number_for_ply(ply.ply_index) > 1 {
+                last_spent = Some(raw_spent);
+            }
         }
 
         match ply.mover {
@@ -62,50 +157,296 @@ pub fn derive_clock_before_and_think_times(
             Color::Black => last_black_after = ply.clock_after_secs,
         }
     }
+
+    if !has_readings {
+        anomalies.clear();
+    }
+    let mut diagnostics = ClockDiagnostics {
+        anomalies,
+        confidence: 0.0,
+        warnings: Vec::new(),
+    };
+    let clean = plies
+        .iter()
+        .filter(|p| p.think_time_secs.is_some() && !diagnostics.is_suspect(p.ply_index))
+        .count();
+    if !plies.is_empty() {
+        diagnostics.confidence = clean as f32 / plies.len() as f32;
+    }
+    diagnostics
+}
+
+/// Base times offered by Lichess, Chess.com and common OTB controls, in seconds.
+const COMMON_BASE_SECS: [u32; 20] = [
+    15, 30, 45, 60, 90, 120, 180, 300, 420, 600, 900, 1200, 1500, 1800, 2700, 3600, 4500, 5400,
+    6000, 7200,
+];
+
+/// Inferred time controls below this confidence are discarded: a guess from a handful of noisy
+/// readings would drive every think time and time-equity value of the game.
+pub const MIN_INFERRED_CONFIDENCE: f32 = 0.5;
+
+#[derive(Debug, Clone)]
+pub struct InferredTimeControl {
+    pub time_control: TimeControl,
+    /// In `[0, 1]`: grows with the number of readings, with a snap to a common base, and with how
+    /// few clock rises exceed the inferred increment.
+    pub confidence: f32,
+}
+
+/// Estimates a sudden-death time control from raw `[%clk]` readings. The increment is the largest
+/// rise between a player's consecutive readings seen at least twice, since near-instant moves gain
+/// about the full increment; the base comes from the highest early reading, snapped to a common
+/// base when close.
+pub fn infer_time_control(plies: &[PlyRecord]) -> Option<InferredTimeControl> {
+    let readings = |color: Color| -> Vec<f32> {
+        plies
+            .iter()
+            .filter(|p| p.mover == color)
+            .filter_map(|p| p.clock_after_secs)
+            .collect()
+    };
+    let white = readings(Color::White);
+    let black = readings(Color::Black);
+    let count = white.len() + black.len();
+    if count == 0 {
+        return None;
+    }
+
+    let rises: Vec<f32> = [&white, &black]
+        .iter()
+        .flat_map(|r| r.windows(2).map(|w| w[1] - w[0]))
+        .collect();
+    let mut positive: Vec<i64> = rises
+        .iter()
+        .map(|d| d.round() as i64)
+        .filter(|d| *d > 0)
+        .collect();
+    positive.sort_unstable_by(|a, b| b.cmp(a));
+    let increment = positive
+        .windows(2)
+        .find(|w| w[0] == w[1])
+        .map(|w| w[0] as f32)
+        .unwrap_or(0.0);
+
+    let early_max = white
+        .iter()
+        .take(2)
+        .chain(black.iter().take(2))
+        .copied()
+        .fold(0.0f32, f32::max);
+    // A reading can exceed the base by at most one increment; early moves rarely take long.
+    let lowest = early_max - increment - CLOCK_TOLERANCE_SECS;
+    let slack = (early_max * 0.05).max(5.0);
+    let common = COMMON_BASE_SECS
+        .iter()
+        .copied()
+        .find(|b| *b as f32 >= lowest && *b as f32 <= early_max + slack);
+    let snapped = common.is_some();
+    let base = common.unwrap_or(early_max.round() as u32);
+
+    let consistent = if rises.is_empty() {
+        1.0
+    } else {
+        let ok = rises
+            .iter()
+            .filter(|d| **d <= increment + CLOCK_TOLERANCE_SECS)
+            .count();
+        ok as f32 / rises.len() as f32
+    };
+    let coverage = (count as f32 / 10.0).min(1.0);
+    let snap_factor = if snapped { 1.0 } else { 0.6 };
+
+    Some(InferredTimeControl {
+        time_control: TimeControl::sudden_death(base, increment as u32),
+        confidence: coverage * snap_factor * consistent,
+    })
+}
+
+/// Fills think times lost to missing `[%clk]` readings. The time a player used between two known
+/// readings is spread evenly over the plies in between, which get estimated clocks and are marked
+/// with `think_time_estimated`. Assumes increments are credited after the move; gaps at the end of
+/// the game and in per-move reset controls are left empty.
+pub fn interpolate_missing_clocks(plies: &mut [PlyRecord], tc: Option<&TimeControl>) {
+    for color in [Color::White, Color::Black] {
+        let mut anchor = tc.map(|t| t.base_secs_for(color) as f32);
+        let mut gap: Vec<usize> = Vec::new();
+
+        for idx in 0..plies.len() {
+            if plies[idx].mover != color {
+                continue;
+            }
+            if ClockCredit::for_ply(tc, plies[idx].ply_index)
+                .reset_secs
+                .is_some()
+            {
+                gap.clear();
+                continue;
+            }
+            let Some(t_after) = plies[idx].clock_after_secs else {
+                if anchor.is_some() {
+                    gap.push(idx);
+                }
+                continue;
+            };
+            if let (Some(t_start), false) = (anchor, gap.is_empty()) {
+                fill_gap(plies, tc, &gap, idx, t_start, t_after);
+            }
+            gap.clear();
+            anchor = Some(t_after);
+        }
+    }
+}
+
+/// Spreads the clock drop from `t_start` to `t_end` over the `gap` plies and, unless its think
+/// time is already known, the ply at `end_idx` that carries `t_end`.
+fn fill_gap(
+    plies: &mut [PlyRecord],
+    tc: Option<&TimeControl>,
+    gap: &[usize],
+    end_idx: usize,
+    t_start: f32,
+    t_end: f32,
+) {
+    let end_known = plies[end_idx].think_time_secs;
+    let mut slots: Vec<usize> = gap.to_vec();
+    if end_known.is_none() {
+        slots.push(end_idx);
+    }
+
+    let credits: f32 = gap
+        .iter()
+        .chain(std::iter::once(&end_idx))
+        .map(|&i| {
+            let credit = ClockCredit::for_ply(tc, plies[i].ply_index);
+            credit.increment + credit.bonus
+        })
+        .sum();
+    let end_deducted = end_known
+        .map(|t| (t - ClockCredit::for_ply(tc, plies[end_idx].ply_index).delay).max(0.0))
+        .unwrap_or(0.0);
+    let deducted = t_start + credits - t_end - end_deducted;
+    if deducted < 0.0 {
+        return;
+    }
+    let share = deducted / slots.len() as f32;
+
+    let mut t_before = t_start;
+    for &i in gap {
+        let credit = ClockCredit::for_ply(tc, plies[i].ply_index);
+        let t_after = (t_before + credit.increment + credit.bonus - share).max(0.0);
+        let ply = &mut plies[i];
+        ply.clock_before_secs.get_or_insert(t_before);
+        ply.clock_after_secs = Some(t_after);
+        ply.think_time_secs = Some(credit.spent(t_before, t_after, true).max(0.0));
+        ply.think_time_estimated = true;
+        t_before = t_after;
+    }
+
+    let credit = ClockCredit::for_ply(tc, plies[end_idx].ply_index);
+    let ply = &mut plies[end_idx];
+    ply.clock_before_secs = Some(t_before);
+    if end_known.is_none() {
+        ply.think_time_secs = Some(credit.spent(t_before, t_end, true).max(0.0));
+        ply.think_time_estimated = true;
+    }
+}
+
+/// Time the clock gains around one move: the period's increment, any bonus for completing a
+/// period (e.g. after move 40), a per-move reset for correspondence controls, or the delay window
+/// that is never deducted.
+#[derive(Debug, Clone, Copy, Default)]
+struct ClockCredit {
+    increment: f32,
+    bonus: f32,
+    reset_secs: Option<f32>,
+    delay: f32,
+}
+
+impl ClockCredit {
+    fn for_ply(tc: Option<&TimeControl>, ply_index: u32) -> Self {
+        let Some(tc) = tc else {
+            return Self::default();
+        };
+        let move_number = move_number_for_ply(ply_index);
+        Self {
+            increment: tc.increment_for_move(move_number) as f32,
+            bonus: tc.bonus_after_move(move_number) as f32,
+            reset_secs: tc.per_move_clock_secs(move_number).map(|s| s as f32),
+            delay: tc.delay_secs() as f32,
+        }
+    }
+
+    /// With a delay, any deduction means the whole window was used first. A move with nothing
+    /// deducted fell inside the window; its think time is unknown and reported as the deduction.
+    fn spent(self, t_before: f32, t_after: f32, increment_applied: bool) -> f32 {
+        let inc = if increment_applied {
+            self.increment
+        } else {
+            0.0
+        };
+        let deducted = t_before + inc + self.bonus - t_after;
+        if self.delay > 0.0 && deducted > 0.0 {
+            deducted + self.delay
+        } else {
+            deducted
+        }
+    }
+
+    fn rebuild_clock_after(self, t_before: f32, emt: f32, policy: ClockPolicy) -> f32 {
+        let inc = if policy.increment_applied_after_move {
+            self.increment
+        } else {
+            0.0
+        };
+        (t_before + inc + self.bonus - (emt - self.delay).max(0.0)).max(0.0)
+    }
 }
 
 fn infer_policy(
     plies: &[PlyRecord],
-    base: Option<f32>,
-    inc: f32,
+    tc: Option<&TimeControl>,
     default_policy: ClockPolicy,
 ) -> ClockPolicy {
-    let applied_penalty = policy_penalty(plies, base, inc, true);
-    let raw_penalty = policy_penalty(plies, base, inc, false);
+    let applied_penalty = policy_penalty(plies, tc, true);
+    let raw_penalty = policy_penalty(plies, tc, false);
 
     if applied_penalty < raw_penalty {
         ClockPolicy {
             increment_applied_after_move: true,
+            ..default_policy
         }
     } else if raw_penalty < applied_penalty {
         ClockPolicy {
             increment_applied_after_move: false,
+            ..default_policy
         }
     } else {
         default_policy
     }
 }
 
-fn policy_penalty(plies: &[PlyRecord], base: Option<f32>, inc: f32, applied: bool) -> u32 {
-    let mut last_white_after: Option<f32> = None;
-    let mut last_black_after: Option<f32> = None;
+fn policy_penalty(plies: &[PlyRecord], tc: Option<&TimeControl>, applied: bool) -> u32 {
+    let mut last_white_after = tc.map(|t| t.base_secs_for(Color::White) as f32);
+    let mut last_black_after = tc.map(|t| t.base_secs_for(Color::Black) as f32);
     let mut penalty = 0;
-    let tolerance = 0.5;
+    let tolerance = CLOCK_TOLERANCE_SECS;
 
     for ply in plies.iter() {
-        let t_before = match ply.mover {
-            Color::White => last_white_after.or(base),
-            Color::Black => last_black_after.or(base),
-        };
+        let credit = ClockCredit::for_ply(tc, ply.ply_index);
+        let t_before = credit.reset_secs.or(match ply.mover {
+            Color::White => last_white_after,
+            Color::Black => last_black_after,
+        });
 
         if let (Some(t_before), Some(t_after)) = (t_before, ply.clock_after_secs) {
-            let spent = if applied {
-                (t_before + inc) - t_after
+            let spent = credit.spent(t_before, t_after, applied);
+
+            let max_spent = if applied {
+                t_before + credit.increment + credit.delay
             } else {
-                t_before - t_after
+                t_before + credit.delay
             };
-
-            let max_spent = if applied { t_before + inc } else { t_before };
             if spent < -tolerance || spent > max_spent + tolerance {
                 penalty += 1;
             }
@@ -1,6 +1,7 @@
 pub mod parse;
 pub mod uci;
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -9,6 +10,46 @@ pub enum EngineError {
     Io(#[from] std::io::Error),
     #[error("protocol error: {0}")]
     Protocol(String),
-    #[error("timeout")]
-    Timeout,
+    #[error("engine did not respond within {waited_ms} ms{}", stderr_suffix(.stderr))]
+    Timeout { waited_ms: u64, stderr: String },
+    #[error("engine exited{}", stderr_suffix(.stderr))]
+    Exited { stderr: String },
+}
+
+impl EngineError {
+    /// The last lines the engine wrote to stderr before it stalled or exited, if any.
+    pub fn stderr(&self) -> Option<&str> {
+        match self {
+            Self::Timeout { stderr, .. } | Self::Exited { stderr } if !stderr.is_empty() => {
+                Some(stderr)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn stderr_suffix(stderr: &str) -> String {
+    if stderr.is_empty() {
+        String::new()
+    } else {
+        format!("; stderr: {}", stderr)
+    }
+}
+
+/// How long to wait on the engine before treating it as hung.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineTimeouts {
+    /// The `uci` handshake and every `isready` round-trip.
+    pub handshake_ms: u64,
+    /// Longest silence during a search; `go movetime` searches get their movetime on top.
+    pub read_ms: u64,
+}
+
+impl Default for EngineTimeouts {
+    fn default() -> Self {
+        Self {
+            handshake_ms: 2_000,
+            read_ms: 10_000,
+        }
+    }
 }
@@ -1,4 +1,14 @@
-use crate::model::{EngineLine, EngineSummary};
+use crate::model::{EngineLine, EngineSummary, Score, Wdl};
+
+/// The name from an `option name <name> type <type> ...` line of the `uci` handshake.
+pub fn parse_option_name(line: &str) -> Option<String> {
+    let rest = line.strip_prefix("option name ")?;
+    let name = match rest.find(" type ") {
+        Some(end) => &rest[..end],
+        None => rest,
+    };
+    Some(name.trim().to_string()).filter(|name| !name.is_empty())
+}
 
 pub struct UciInfoAccumulator {
     target_multipv: u8,
@@ -29,10 +39,12 @@ impl UciInfoAccumulator {
 
         let mut i = 1;
         let mut depth: Option<u16> = None;
+        let mut seldepth: Option<u16> = None;
         let mut multipv: u8 = 1;
         let mut score_kind: Option<&str> = None;
         let mut score_val: Option<i32> = None;
-        let mut first_pv: Option<String> = None;
+        let mut wdl: Option<Wdl> = None;
+        let mut pv: Vec<String> = Vec::new();
 
         while i < tokens.len() {
             match tokens[i] {
@@ -42,6 +54,12 @@ impl UciInfoAccumulator {
                     }
                     i += 2;
                 }
+                "seldepth" => {
+                    if i + 1 < tokens.len() {
+                        seldepth = tokens[i + 1].parse::<u16>().ok();
+                    }
+                    i += 2;
+                }
                 "multipv" => {
                     if i + 1 < tokens.len() {
                         multipv = tokens[i + 1].parse::<u8>().unwrap_or(1);
@@ -55,6 +73,18 @@ impl UciInfoAccumulator {
                     }
                     i += 3;
                 }
+                "wdl" => {
+                    if i + 3 < tokens.len() {
+                        let parsed: Vec<u16> = tokens[i + 1..i + 4]
+                            .iter()
+                            .filter_map(|t| t.parse::<u16>().ok())
+                            .collect();
+                        if let [win, draw, loss] = parsed[..] {
+                            wdl = Some(Wdl { win, draw, loss });
+                        }
+                    }
+                    i += 4;
+                }
                 "nodes" => {
                     if i + 1 < tokens.len() {
                         self.nodes = tokens[i + 1].parse::<u64>().unwrap_or(self.nodes);
@@ -68,9 +98,7 @@ impl UciInfoAccumulator {
                     i += 2;
                 }
                 "pv" => {
-                    if i + 1 < tokens.len() {
-                        first_pv = Some(tokens[i + 1].to_string());
-                    }
+                    pv = tokens[i + 1..].iter().map(|t| t.to_string()).collect();
                     break;
                 }
                 _ => i += 1,
@@ -83,12 +111,12 @@ impl UciInfoAccumulator {
             }
         }
 
-        if let (Some(kind), Some(val), Some(pv)) = (score_kind, score_val, first_pv) {
+        let first_pv = pv.first().cloned();
+        if let (Some(kind), Some(val), Some(first)) = (score_kind, score_val, first_pv) {
             let (cp_white, mate) = if kind == "cp" {
                 (val, None)
             } else {
-                let sign = if val >= 0 { 1 } else { -1 };
-                (sign * 100_000, Some(val))
+                (Score::Mate(val).cp(), Some(val))
             };
 
             if multipv >= 1 && multipv <= self.target_multipv {
@@ -98,9 +126,13 @@ impl UciInfoAccumulator {
                     self.line_depths[idx] = depth_val;
                     self.lines[idx] = Some(EngineLine {
                         multipv,
-                        uci: pv,
+                        uci: first,
                         cp_white,
                         mate,
+                        wdl,
+                        seldepth,
+                        pv_uci: pv,
+                        pv_san: Vec::new(),
                     });
                 }
             }
@@ -118,6 +150,8 @@ impl UciInfoAccumulator {
             lines,
             played_cp_white: None,
             best_cp_white: None,
+            played_score_white: None,
+            best_score_white: None,
             punish_cp_mover: None,
             spread_k_cp_mover: None,
             gap_12_cp_mover: None,
@@ -1,23 +1,39 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    process::{Child, Command},
-    time::{timeout, Duration},
+    process::{Child, ChildStderr, Command},
+    task::JoinHandle,
+    time::{error::Elapsed, timeout, Duration},
+};
+
+use crate::engine::{
+    parse::{parse_option_name, UciInfoAccumulator},
+    EngineError, EngineTimeouts,
 };
+use crate::model::{EngineInfo, EngineSummary, SearchLimits};
 
-use crate::engine::{parse::UciInfoAccumulator, EngineError};
-use crate::model::EngineSummary;
+/// Lines of engine stderr kept for error reports.
+const STDERR_TAIL_LINES: usize = 20;
 
 pub struct UciEngine {
     _child: Child,
     stdin: tokio::process::ChildStdin,
     stdout: BufReader<tokio::process::ChildStdout>,
+    stderr_tail: Arc<Mutex<VecDeque<String>>>,
+    stderr_task: Option<JoinHandle<()>>,
+    timeouts: EngineTimeouts,
+    options: Vec<String>,
+    info: EngineInfo,
 }
 
 impl UciEngine {
-    pub async fn start(path: &str) -> Result<Self, EngineError> {
+    pub async fn start(path: &str, timeouts: EngineTimeouts) -> Result<Self, EngineError> {
         let mut child = Command::new(path)
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
             .spawn()?;
 
         let stdin = child
@@ -28,31 +44,62 @@ impl UciEngine {
             .stdout
             .take()
             .ok_or_else(|| EngineError::Protocol("missing stdout".into()))?;
+        let stderr_tail = Arc::new(Mutex::new(VecDeque::new()));
+        let stderr_task = child
+            .stderr
+            .take()
+            .map(|stderr| spawn_stderr_reader(stderr, Arc::clone(&stderr_tail)));
 
         let mut engine = Self {
             _child: child,
             stdin,
             stdout: BufReader::new(stdout),
+            stderr_tail,
+            stderr_task,
+            timeouts,
+            options: Vec::new(),
+            info: EngineInfo::default(),
         };
 
         engine.send("uci").await?;
-        engine.wait_for("uciok", Duration::from_secs(2)).await?;
+        engine.read_handshake().await?;
         engine.send("isready").await?;
-        engine.wait_for("readyok", Duration::from_secs(2)).await?;
+        engine.wait_for("readyok").await?;
 
         Ok(engine)
     }
 
+    /// Whether the engine advertised `name` during the handshake (UCI names are case-insensitive).
+    pub fn supports_option(&self, name: &str) -> bool {
+        self.options.iter().any(|o| o.eq_ignore_ascii_case(name))
+    }
+
+    /// The engine's `id` lines and every option set so far.
+    pub fn info(&self) -> &EngineInfo {
+        &self.info
+    }
+
     pub async fn set_option(&mut self, name: &str, value: &str) -> Result<(), EngineError> {
         self.send(&format!("setoption name {} value {}", name, value))
             .await?;
+        self.info
+            .options
+            .insert(name.to_string(), value.to_string());
         Ok(())
     }
 
+    /// Sends an option that follows the game rather than the run (`UCI_Variant`, `UCI_Chess960`).
+    /// Unlike `set_option` it is not recorded in `info().options`, which describe the engine
+    /// setup shared by every game.
+    pub async fn set_game_option(&mut self, name: &str, value: &str) -> Result<(), EngineError> {
+        self.send(&format!("setoption name {} value {}", name, value))
+            .await
+    }
+
     pub async fn new_game(&mut self) -> Result<(), EngineError> {
         self.send("ucinewgame").await?;
         self.send("isready").await?;
-        self.wait_for("readyok", Duration::from_secs(2)).await?;
+        self.wait_for("readyok").await?;
         Ok(())
     }
 
@@ -63,15 +110,18 @@ impl UciEngine {
 
     pub async fn go_multipv(
         &mut self,
-        depth: u16,
-        movetime_ms: Option<u64>,
-        multipv: u8,
+        limits: &SearchLimits,
         searchmoves: Option<&str>,
     ) -> Result<EngineSummary, EngineError> {
-        let mut cmd = if let Some(ms) = movetime_ms {
-            format!("go movetime {}", ms)
-        } else {
-            format!("go depth {}", depth)
+        let mut cmd = match (limits.nodes, limits.movetime_ms, limits.depth) {
+            (Some(nodes), _, _) => format!("go nodes {}", nodes),
+            (None, Some(ms), _) => format!("go movetime {}", ms),
+            (None, None, Some(depth)) => format!("go depth {}", depth),
+            (None, None, None) => {
+                return Err(EngineError::Protocol(
+                    "search limits set no depth, movetime or nodes".into(),
+                ))
+            }
         };
 
         if let Some(sm) = searchmoves {
@@ -81,9 +131,11 @@ impl UciEngine {
 
         self.send(&cmd).await?;
 
-        let mut acc = UciInfoAccumulator::new(multipv);
+        let silence =
+            Duration::from_millis(self.timeouts.read_ms + limits.movetime_ms.unwrap_or(0));
+        let mut acc = UciInfoAccumulator::new(limits.multipv);
         loop {
-            let line = self.read_line(Duration::from_secs(10)).await?;
+            let line = self.read_line(silence).await?;
             if line.starts_with("info ") {
                 acc.ingest_line(&line);
             } else if line.starts_with("bestmove ") {
@@ -100,44 +152,118 @@ impl UciEngine {
     }
 
     async fn send(&mut self, s: &str) -> Result<(), EngineError> {
-        self.stdin.write_all(s.as_bytes()).await?;
-        self.stdin.write_all(b"\n").await?;
-        self.stdin.flush().await?;
-        Ok(())
+        let written = async {
+            self.stdin.write_all(s.as_bytes()).await?;
+            self.stdin.write_all(b"\n").await?;
+            self.stdin.flush().await
+        }
+        .await;
+        match written {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Err(self.exited().await),
+            other => Ok(other?),
+        }
     }
 
-    async fn wait_for(&mut self, token: &str, dur: Duration) -> Result<(), EngineError> {
+    async fn wait_for(&mut self, token: &str) -> Result<(), EngineError> {
+        let dur = Duration::from_millis(self.timeouts.handshake_ms);
         let fut = async {
             loop {
-                let mut buf = String::new();
-                let n = self.stdout.read_line(&mut buf).await?;
-                if n == 0 {
-                    return Err(EngineError::Protocol("engine exited".into()));
-                }
-                if buf.trim() == token {
+                if self.read_line_raw().await? == token {
                     return Ok(());
                 }
             }
         };
-        timeout(dur, fut)
-            .await
-            .map_err(|_| EngineError::Timeout)??;
-        Ok(())
+        let result = timeout(dur, fut).await;
+        self.settle(dur, result).await
     }
 
-    async fn read_line(&mut self, dur: Duration) -> Result<String, EngineError> {
+    /// Reads the `uci` handshake up to `uciok`, keeping the `id` lines and the advertised option
+    /// names.
+    async fn read_handshake(&mut self) -> Result<(), EngineError> {
+        let dur = Duration::from_millis(self.timeouts.handshake_ms);
         let fut = async {
-            let mut buf = String::new();
-            let n = self.stdout.read_line(&mut buf).await?;
-            if n == 0 {
-                return Err(EngineError::Protocol("engine exited".into()));
+            loop {
+                let line = self.read_line_raw().await?;
+                if line == "uciok" {
+                    return Ok(());
+                }
+                if let Some(name) = line.strip_prefix("id name ") {
+                    self.info.name = Some(name.trim().to_string());
+                } else if let Some(author) = line.strip_prefix("id author ") {
+                    self.info.author = Some(author.trim().to_string());
+                } else {
+                    self.options.extend(parse_option_name(&line));
+                }
             }
-            Ok(buf.trim().to_string())
         };
-        timeout(dur, fut)
-            .await
-            .map_err(|_| EngineError::Timeout)?
+        let result = timeout(dur, fut).await;
+        self.settle(dur, result).await
+    }
+
+    async fn read_line(&mut self, dur: Duration) -> Result<String, EngineError> {
+        let result = timeout(dur, self.read_line_raw()).await;
+        self.settle(dur, result).await
+    }
+
+    /// One trimmed line of stdout; end of output is reported as `Exited` without stderr, which
+    /// `settle` fills in.
+    async fn read_line_raw(&mut self) -> Result<String, EngineError> {
+        let mut buf = String::new();
+        let n = self.stdout.read_line(&mut buf).await?;
+        if n == 0 {
+            return Err(EngineError::Exited {
+                stderr: String::new(),
+            });
+        }
+        Ok(buf.trim().to_string())
+    }
+
+    /// Turns a timed-out or failed read into an error carrying the engine's stderr.
+    async fn settle<T>(
+        &mut self,
+        dur: Duration,
+        result: Result<Result<T, EngineError>, Elapsed>,
+    ) -> Result<T, EngineError> {
+        match result {
+            Ok(Err(EngineError::Exited { .. })) => Err(self.exited().await),
+            Ok(result) => result,
+            Err(_) => Err(EngineError::Timeout {
+                waited_ms: dur.as_millis() as u64,
+                stderr: self.stderr_snapshot(),
+            }),
+        }
+    }
+
+    /// An `Exited` error, after giving the stderr reader a moment to drain the final lines.
+    async fn exited(&mut self) -> EngineError {
+        if let Some(task) = self.stderr_task.take() {
+            let _ = timeout(Duration::from_millis(200), task).await;
+        }
+        EngineError::Exited {
+            stderr: self.stderr_snapshot(),
+        }
     }
+
+    fn stderr_snapshot(&self) -> String {
+        let tail = self
+            .stderr_tail
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        tail.iter().cloned().collect::<Vec<_>>().join(" | ")
+    }
+}
+
+fn spawn_stderr_reader(stderr: ChildStderr, tail: Arc<Mutex<VecDeque<String>>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let mut tail = tail.lock().unwrap_or_else(PoisonError::into_inner);
+            if tail.len() == STDERR_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+    })
 }
 
 impl Drop for UciEngine {
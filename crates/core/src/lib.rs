@@ -5,5 +5,5 @@ pub mod model;
 pub mod pgn;
 pub mod utils;
 
-pub use analysis::pipeline::{analyze_pgn, AnalysisConfig};
 pub use analysis::pipeline::analyze_pgns;
+pub use analysis::pipeline::{analyze_pgn, AnalysisConfig};
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Color {
@@ -10,10 +10,162 @@ pub enum Color {
 pub const PHASE_OPENING_END_PLY: u32 = 20;
 pub const PHASE_MIDDLEGAME_END_PLY: u32 = 60;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A time control made of one or more periods. The first period is described by the top-level
+/// fields; `later_stages` follow it in order, and the last one repeats if it has a move count.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TimeControl {
     pub base_secs: u32,
     pub increment_secs: u32,
+    /// Moves to play within `base_secs`; `None` means sudden death.
+    pub moves: Option<u32>,
+    pub later_stages: Vec<TimeControlStage>,
+    pub delay: Option<TimeDelay>,
+    /// Black's starting clock when it differs from White's (time odds, Armageddon).
+    pub black_base_secs: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DelayKind {
+    /// US delay: the clock starts after the delay window.
+    Simple,
+    /// Bronstein: time used is refunded up to the delay after the move.
+    Bronstein,
+}
+
+/// Per-move delay. Both kinds leave the same reading after a move: the first `secs` of each move
+/// are free.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeDelay {
+    pub kind: DelayKind,
+    pub secs: u32,
+}
+
+/// A period added to the clock once the previous period's moves have been played.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimeControlStage {
+    pub moves: Option<u32>,
+    pub base_secs: u32,
+    pub increment_secs: u32,
+}
+
+impl TimeControl {
+    pub fn sudden_death(base_secs: u32, increment_secs: u32) -> Self {
+        Self {
+            base_secs,
+            increment_secs,
+            moves: None,
+            later_stages: Vec::new(),
+            delay: None,
+            black_base_secs: None,
+        }
+    }
+
+    pub fn base_secs_for(&self, color: Color) -> u32 {
+        match color {
+            Color::White => self.base_secs,
+            Color::Black => self.black_base_secs.unwrap_or(self.base_secs),
+        }
+    }
+
+    pub fn delay_secs(&self) -> u32 {
+        self.delay.map(|d| d.secs).unwrap_or(0)
+    }
+
+    fn first_stage(&self) -> TimeControlStage {
+        TimeControlStage {
+            moves: self.moves,
+            base_secs: self.base_secs,
+            increment_secs: self.increment_secs,
+        }
+    }
+
+    fn stage(&self, idx: usize) -> TimeControlStage {
+        if idx == 0 {
+            return self.first_stage();
+        }
+        let later = idx - 1;
+        self.later_stages
+            .get(later)
+            .or_else(|| self.later_stages.last())
+            .cloned()
+            .unwrap_or_else(|| self.first_stage())
+    }
+
+    /// Index of the period governing the player's `move_number` (1-based) and the last move
+    /// number in it (`None` for sudden death).
+    fn stage_index_for_move(&self, move_number: u32) -> (usize, Option<u32>) {
+        let mut idx = 0usize;
+        let mut start = 0u32;
+        loop {
+            let stage = self.stage(idx);
+            let moves = match stage.moves {
+                Some(m) if m > 0 => m,
+                _ => return (idx, None),
+            };
+            let end = start + moves;
+            if move_number <= end {
+                return (idx, Some(end));
+            }
+            start = end;
+            idx += 1;
+        }
+    }
+
+    pub fn has_increment(&self) -> bool {
+        self.increment_secs > 0 || self.later_stages.iter().any(|s| s.increment_secs > 0)
+    }
+
+    pub fn increment_for_move(&self, move_number: u32) -> u32 {
+        let (idx, _) = self.stage_index_for_move(move_number);
+        self.stage(idx).increment_secs
+    }
+
+    /// Time credited once `move_number` completes its period, e.g. the 30 minutes after move 40
+    /// in `40/5400+30:1800+30`. Single-move periods reset the clock instead, so they add nothing.
+    pub fn bonus_after_move(&self, move_number: u32) -> u32 {
+        let (idx, end) = self.stage_index_for_move(move_number);
+        if end != Some(move_number) {
+            return 0;
+        }
+        let next = self.stage(idx + 1);
+        if next.moves == Some(1) {
+            0
+        } else {
+            next.base_secs
+        }
+    }
+
+    /// Clock at the start of `move_number` when the period resets it every move, as in
+    /// correspondence `1/259200` (days per move).
+    pub fn per_move_clock_secs(&self, move_number: u32) -> Option<u32> {
+        let (idx, _) = self.stage_index_for_move(move_number);
+        let stage = self.stage(idx);
+        (stage.moves == Some(1)).then_some(stage.base_secs)
+    }
+}
+
+/// A player's own move number for a 1-based ply index.
+pub fn move_number_for_ply(ply_index: u32) -> u32 {
+    ply_index.div_ceil(2)
+}
+
+/// Numbers a SAN line starting at `first_ply_index`, e.g. `23...Nxe4 24.Bxe4 Qh4`.
+pub fn format_san_line(first_ply_index: u32, sans: &[String]) -> String {
+    let mut out = String::new();
+    for (offset, san) in sans.iter().enumerate() {
+        let ply_index = first_ply_index + offset as u32;
+        let white = ply_index % 2 == 1;
+        if offset > 0 {
+            out.push(' ');
+        }
+        if white {
+            out.push_str(&format!("{}.", move_number_for_ply(ply_index)));
+        } else if offset == 0 {
+            out.push_str(&format!("{}...", move_number_for_ply(ply_index)));
+        }
+        out.push_str(san);
+    }
+    out
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -23,6 +175,16 @@ pub enum SourcePlatform {
     Unknown,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TimeControlSource {
+    /// The game's `TimeControl` header (or its `WhiteClock`/`BlackClock` headers).
+    Header,
+    /// `AnalysisConfig::fallback_time_control` or the starting-clock overrides.
+    Fallback,
+    /// Estimated from the `[%clk]` readings.
+    Inferred,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameMeta {
     pub event: Option<String>,
@@ -33,8 +195,36 @@ pub struct GameMeta {
     pub black: Option<String>,
     pub result: Option<String>,
     pub time_control: Option<TimeControl>,
+    pub time_control_source: Option<TimeControlSource>,
+    /// How well the clock readings fit an inferred time control, in `[0, 1]`.
+    pub time_control_confidence: Option<f32>,
+    /// Draws count as wins for Black.
+    pub armageddon: bool,
+    /// The engine search requested for every position; absent when no engine was used.
+    pub search_limits: Option<SearchLimits>,
+    /// Engine identity and options at the time of analysis; absent when no engine was used.
+    pub engine: Option<EngineInfo>,
+    /// `AnalysisConfig::config_hash` of the run.
+    pub config_hash: String,
+    /// Times the engine was restarted after crashing or stalling during this game.
+    pub engine_restarts: u32,
     pub platform: SourcePlatform,
     pub headers: HashMap<String, String>,
+    /// Parts of the PGN that were left out, such as sidelines cut short at an illegal move.
+    pub warnings: Vec<String>,
+}
+
+impl GameMeta {
+    /// Whether two analyses came from the same engine build, options and settings, so their
+    /// numbers can be aggregated together.
+    pub fn comparable_with(&self, other: &GameMeta) -> bool {
+        let engine_id = |meta: &GameMeta| {
+            meta.engine
+                .as_ref()
+                .map(|e| (e.name.clone(), e.options.clone()))
+        };
+        self.config_hash == other.config_hash && engine_id(self) == engine_id(other)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,14 +239,176 @@ pub struct PlyRecord {
     pub clock_after_secs: Option<f32>,
     pub clock_before_secs: Option<f32>,
     pub think_time_secs: Option<f32>,
+    /// True when the think time (and any filled-in clocks) came from gap interpolation.
+    pub think_time_estimated: bool,
+    /// The move was most likely premoved, judging by the platform's clock model.
+    pub premove: bool,
+    /// True when `premove` was inferred from whole-second readings, where a 0s move can also be
+    /// a fast move rounded down.
+    pub premove_estimated: bool,
+    pub elapsed_move_secs: Option<f32>,
+    pub comment: Option<String>,
+    /// Sidelines replacing this ply, built from `fen_before`.
+    pub variations: Vec<Vec<PlyRecord>>,
+}
+
+/// The engine behind an analysis: its `id` lines and the UCI options set for the whole run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineInfo {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub options: BTreeMap<String, String>,
+}
+
+/// How long each position is searched. Exactly one of `depth`, `movetime_ms` and `nodes` is set:
+/// the mode the engine was actually asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchLimits {
+    pub depth: Option<u16>,
+    pub movetime_ms: Option<u64>,
+    pub nodes: Option<u64>,
+    pub multipv: u8,
+}
+
+impl SearchLimits {
+    /// Whether a summary searched with `self` can answer a request for `other`: at least as many
+    /// lines, and at least as deep (or as long) in the same search mode. Node-limited searches
+    /// are meant to be reproducible, so they only match the exact same node count and MultiPV.
+    pub fn covers(&self, other: &SearchLimits) -> bool {
+        match (self.nodes, other.nodes) {
+            (Some(mine), Some(theirs)) => return mine == theirs && self.multipv == other.multipv,
+            (None, None) => {}
+            _ => return false,
+        }
+        if self.multipv < other.multipv {
+            return false;
+        }
+        match (self.movetime_ms, other.movetime_ms) {
+            (None, None) => self.depth >= other.depth,
+            (Some(mine), Some(theirs)) => mine >= theirs,
+            _ => false,
+        }
+    }
+}
+
+/// Centipawn stand-in for a mate in `cp_white` fields, which keeps lines sortable.
+pub const MATE_CP: i32 = 100_000;
+
+/// What a forced mate counts for in punishment, complexity and their averages. Centipawn scores
+/// are clamped to the same bound, so one mate swing cannot dominate a game's numbers.
+pub const MATE_METRIC_CP: i32 = 2_000;
+
+/// An engine score with mates kept apart from centipawns. Mate distances are in moves and
+/// positive when White (the mover, for `for_mover`) delivers it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Score {
+    Cp(i32),
+    Mate(i32),
+}
+
+impl Score {
+    pub fn from_cp_and_mate(cp: i32, mate: Option<i32>) -> Self {
+        match mate {
+            Some(moves) => Score::Mate(moves),
+            None => Score::Cp(cp),
+        }
+    }
+
+    /// The score from `mover`'s side instead of White's.
+    pub fn for_mover(self, mover: Color) -> Self {
+        match (self, mover) {
+            (_, Color::White) => self,
+            (Score::Cp(cp), Color::Black) => Score::Cp(-cp),
+            (Score::Mate(moves), Color::Black) => Score::Mate(-moves),
+        }
+    }
+
+    /// The legacy single-number form, with mates at `±MATE_CP`.
+    pub fn cp(self) -> i32 {
+        match self {
+            Score::Cp(cp) => cp,
+            Score::Mate(moves) if moves > 0 => MATE_CP,
+            Score::Mate(_) => -MATE_CP,
+        }
+    }
+
+    /// The bounded form used for punishment and complexity: any forced mate is worth
+    /// `±MATE_METRIC_CP`, so choosing a slower mate over a faster one costs nothing.
+    pub fn metric_cp(self) -> i32 {
+        match self {
+            Score::Cp(cp) => cp.clamp(-MATE_METRIC_CP, MATE_METRIC_CP),
+            Score::Mate(moves) if moves > 0 => MATE_METRIC_CP,
+            Score::Mate(_) => -MATE_METRIC_CP,
+        }
+    }
+
+    /// Moves to mate when this side is the one delivering it.
+    pub fn mate_in(self) -> Option<u32> {
+        match self {
+            Score::Mate(moves) if moves > 0 => Some(moves as u32),
+            _ => None,
+        }
+    }
+}
+
+/// Engine win/draw/loss estimate in per mille.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Wdl {
+    pub win: u16,
+    pub draw: u16,
+    pub loss: u16,
+}
+
+impl Wdl {
+    /// The same estimate from the other side.
+    pub fn flipped(self) -> Self {
+        Self {
+            win: self.loss,
+            draw: self.draw,
+            loss: self.win,
+        }
+    }
+
+    /// Win plus half the draws, as a probability.
+    pub fn expected_score(&self) -> f32 {
+        self.share(self.win as f32 + self.draw as f32 / 2.0)
+    }
+
+    /// Winning chance alone, for games where a draw counts as a loss.
+    pub fn win_prob(&self) -> f32 {
+        self.share(self.win as f32)
+    }
+
+    fn share(&self, points: f32) -> f32 {
+        let total = self.win as f32 + self.draw as f32 + self.loss as f32;
+        if total <= 0.0 {
+            0.5
+        } else {
+            points / total
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EngineLine {
     pub multipv: u8,
+    /// First move of the PV.
     pub uci: String,
     pub cp_white: i32,
     pub mate: Option<i32>,
+    /// Reported when the engine supports `UCI_ShowWDL`; White's view once normalised.
+    pub wdl: Option<Wdl>,
+    pub seldepth: Option<u16>,
+    /// The whole principal variation as reported by the engine.
+    pub pv_uci: Vec<String>,
+    /// `pv_uci` in SAN, up to the first move that does not replay from the searched position.
+    pub pv_san: Vec<String>,
+}
+
+impl EngineLine {
+    pub fn score(&self) -> Score {
+        Score::from_cp_and_mate(self.cp_white, self.mate)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -68,6 +420,9 @@ pub struct EngineSummary {
 
     pub played_cp_white: Option<i32>,
     pub best_cp_white: Option<i32>,
+    pub played_score_white: Option<Score>,
+    pub best_score_white: Option<Score>,
+    /// Best minus played in `Score::metric_cp` terms.
     pub punish_cp_mover: Option<i32>,
     pub spread_k_cp_mover: Option<i32>,
     pub gap_12_cp_mover: Option<i32>,
@@ -91,13 +446,17 @@ pub struct MoveMetrics {
     pub dp_practical_mover: f32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LabelKind {
     OverthinkSimple,
     UnderthinkCritical,
     WastedThink,
     GoodInvestment,
     SnapBlunder,
+    /// The mover had a forced mate and played a move that no longer forces one.
+    MissedMate,
+    /// A blunder played as a premove: a decision made on the opponent's time, not a rushed think.
+    PremoveBlunder,
     PanicBlunder,
     TimeBlunder,
     TimeTrouble,
@@ -113,12 +472,63 @@ pub struct Label {
     pub tips: Vec<String>,
 }
 
+/// A sideline's first move compared against the move actually played.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlternativeMove {
+    pub san: String,
+    pub uci: String,
+    pub line_san: Vec<String>,
+    pub comment: Option<String>,
+    pub cp_white: Option<i32>,
+    /// Alternative minus played, from the mover's perspective; positive means the sideline was better.
+    pub gain_cp_mover: Option<i32>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlyAnalysis {
     pub ply: PlyRecord,
     pub engine_before: EngineSummary,
     pub metrics: MoveMetrics,
     pub label: Label,
+    pub alternatives: Vec<AlternativeMove>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ClockAnomalyKind {
+    /// No clock reading on this ply although the game has clocks elsewhere.
+    Missing,
+    /// The clock rose by more than the increment and period bonuses allow (lag compensation,
+    /// readings out of order, an increment missing from the TimeControl).
+    UnexpectedGain,
+    /// More time was deducted than the player had available.
+    Overspent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockAnomaly {
+    pub ply_index: u32,
+    pub mover: Color,
+    pub kind: ClockAnomalyKind,
+    /// Seconds gained or overspent; 0 for missing readings.
+    pub magnitude_secs: f32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClockDiagnostics {
+    pub anomalies: Vec<ClockAnomaly>,
+    /// Share of plies with a think time and no gain/overspend anomaly, in `[0, 1]`.
+    pub confidence: f32,
+    /// Clock settings, or an inferred time control, that could not be applied to this game.
+    pub warnings: Vec<String>,
+}
+
+impl ClockDiagnostics {
+    /// Whether the clock readings around `ply_index` are inconsistent (missing ones are not).
+    pub fn is_suspect(&self, ply_index: u32) -> bool {
+        self.anomalies
+            .iter()
+            .any(|a| a.ply_index == ply_index && a.kind != ClockAnomalyKind::Missing)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -126,6 +536,40 @@ pub struct GameAnalysis {
     pub meta: GameMeta,
     pub plies: Vec<PlyAnalysis>,
     pub summary: GameSummary,
+    pub clock_diagnostics: ClockDiagnostics,
+}
+
+/// A game of a batch that could not be analysed; it takes the game's place in the output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameFailure {
+    /// Position of the game in the input, counting from 0.
+    pub game_index: usize,
+    pub error: String,
+    pub headers: HashMap<String, String>,
+}
+
+/// One entry of a batch: the game's analysis, or why it has none.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GameOutcome {
+    Analyzed(Box<GameAnalysis>),
+    Failed(GameFailure),
+}
+
+impl GameOutcome {
+    pub fn analysis(&self) -> Option<&GameAnalysis> {
+        match self {
+            Self::Analyzed(analysis) => Some(analysis),
+            Self::Failed(_) => None,
+        }
+    }
+
+    pub fn into_result(self) -> Result<GameAnalysis, GameFailure> {
+        match self {
+            Self::Analyzed(analysis) => Ok(*analysis),
+            Self::Failed(failure) => Err(failure),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1,10 +1,14 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use once_cell::sync::Lazy;
 use pgn_reader::{BufferedReader, RawComment, RawHeader, SanPlus, Skip, Visitor};
 use regex::Regex;
+use shakmaty::variant::Variant;
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
 
-use crate::model::{SourcePlatform, TimeControl};
+use crate::model::{DelayKind, Score, SourcePlatform, TimeControl, TimeControlStage, TimeDelay};
 
 #[derive(Debug, Clone)]
 pub struct ParsedGame {
@@ -16,7 +20,32 @@ pub struct ParsedGame {
 pub struct RawPly {
     pub san: String,
     pub clock_after_secs: Option<f32>,
+    pub elapsed_move_secs: Option<f32>,
+    pub eval_after: Option<EmbeddedEval>,
     pub comment: Option<String>,
+    /// Alternative lines that replace this ply, each starting from the position before it.
+    pub variations: Vec<Vec<RawPly>>,
+}
+
+/// An evaluation annotated in the PGN (`[%eval 0.34]`, `[%eval #-3]`), always from White's
+/// perspective and describing the position after the move it follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddedEval {
+    Cp(i32),
+    Mate(i32),
+}
+
+impl EmbeddedEval {
+    pub fn cp_white(self) -> i32 {
+        self.score_white().cp()
+    }
+
+    pub fn score_white(self) -> Score {
+        match self {
+            EmbeddedEval::Cp(cp) => Score::Cp(cp),
+            EmbeddedEval::Mate(moves) => Score::Mate(moves),
+        }
+    }
 }
 
 pub fn parse_single_game(pgn: &str) -> Result<ParsedGame> {
@@ -28,24 +57,65 @@ pub fn parse_single_game(pgn: &str) -> Result<ParsedGame> {
 }
 
 pub fn parse_games(pgn: &str) -> Result<Vec<ParsedGame>> {
-    let mut reader = BufferedReader::new_cursor(pgn.as_bytes());
-    let mut games = Vec::new();
+    read_games(pgn.as_bytes()).collect()
+}
 
-    loop {
-        let mut visitor = GameVisitor::default();
-        match reader.read_game(&mut visitor)? {
-            Some(game) => games.push(game),
-            None => break,
-        }
+/// Streams games from any reader one at a time, so large archives and database dumps never need
+/// to be held in memory.
+pub fn read_games<R: Read>(reader: R) -> GameReader<R> {
+    GameReader {
+        reader: BufferedReader::new(reader),
     }
+}
+
+/// Opens a PGN file for streaming, transparently decompressing `.pgn.zst` and `.pgn.gz`.
+pub fn open_pgn_file(path: &Path) -> Result<GameReader<Box<dyn Read + Send>>> {
+    Ok(read_games(open_pgn_source(path)?))
+}
+
+/// Raw (decompressed) PGN bytes of a file, picking the codec from the extension.
+pub fn open_pgn_source(path: &Path) -> Result<Box<dyn Read + Send>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let reader: Box<dyn Read + Send> = match ext.as_str() {
+        "zst" | "zstd" => Box::new(zstd::Decoder::new(file)?),
+        "gz" => Box::new(flate2::read::MultiGzDecoder::new(BufReader::new(file))),
+        _ => Box::new(file),
+    };
+    Ok(reader)
+}
+
+pub struct GameReader<R> {
+    reader: BufferedReader<R>,
+}
+
+impl<R: Read> Iterator for GameReader<R> {
+    type Item = Result<ParsedGame>;
 
-    Ok(games)
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut visitor = GameVisitor::default();
+        self.reader
+            .read_game(&mut visitor)
+            .map_err(Into::into)
+            .transpose()
+    }
 }
 
 #[derive(Default)]
 struct GameVisitor {
     headers: HashMap<String, String>,
     plies: Vec<RawPly>,
+    open_variations: Vec<Vec<RawPly>>,
+}
+
+impl GameVisitor {
+    fn current_line(&mut self) -> &mut Vec<RawPly> {
+        self.open_variations.last_mut().unwrap_or(&mut self.plies)
+    }
 }
 
 impl Visitor for GameVisitor {
@@ -58,26 +128,47 @@ impl Visitor for GameVisitor {
     }
 
     fn san(&mut self, san: SanPlus) {
-        self.plies.push(RawPly {
+        self.current_line().push(RawPly {
             san: san.to_string(),
             clock_after_secs: None,
+            elapsed_move_secs: None,
+            eval_after: None,
             comment: None,
+            variations: Vec::new(),
         });
     }
 
     fn comment(&mut self, comment: RawComment<'_>) {
-        if let Some(last) = self.plies.last_mut() {
+        if let Some(last) = self.current_line().last_mut() {
             let s = String::from_utf8_lossy(comment.as_bytes()).to_string();
             last.clock_after_secs = parse_clk_comment_secs(&s).or(last.clock_after_secs);
+            last.elapsed_move_secs = parse_emt_comment_secs(&s).or(last.elapsed_move_secs);
+            last.eval_after = parse_eval_comment(&s).or(last.eval_after);
             last.comment = Some(s);
         }
     }
 
     fn begin_variation(&mut self) -> Skip {
-        Skip(true)
+        // Skipped variations still reach `end_variation`, so always open a frame to pop.
+        let has_branch = !self.current_line().is_empty();
+        self.open_variations.push(Vec::new());
+        Skip(!has_branch)
+    }
+
+    fn end_variation(&mut self) {
+        let Some(line) = self.open_variations.pop() else {
+            return;
+        };
+        if line.is_empty() {
+            return;
+        }
+        if let Some(branch) = self.current_line().last_mut() {
+            branch.variations.push(line);
+        }
     }
 
     fn end_game(&mut self) -> Self::Result {
+        self.open_variations.clear();
         ParsedGame {
             headers: std::mem::take(&mut self.headers),
             plies: std::mem::take(&mut self.plies),
@@ -102,31 +193,131 @@ pub fn detect_platform(headers: &HashMap<String, String>) -> SourcePlatform {
     SourcePlatform::Unknown
 }
 
+/// Lichess and Chess.com both tag Fischer Random games with `Variant "Chess960"`; older exports
+/// use "Fischerandom" spellings.
+pub fn detect_chess960(headers: &HashMap<String, String>) -> bool {
+    headers
+        .get("Variant")
+        .map(|v| {
+            let v = v.to_lowercase();
+            v.contains("960") || v.contains("fischer")
+        })
+        .unwrap_or(false)
+}
+
+/// Maps the Lichess `Variant` header to a shakmaty variant. Missing headers and standard/960/
+/// "From Position" games are plain chess; `None` means the variant is not supported.
+pub fn detect_variant(headers: &HashMap<String, String>) -> Option<Variant> {
+    if detect_chess960(headers) {
+        // Every spelling `detect_chess960` accepts, not just the ones shakmaty knows.
+        return Some(Variant::Chess);
+    }
+    match headers.get("Variant") {
+        Some(v) => v.trim().parse().ok(),
+        None => Some(Variant::Chess),
+    }
+}
+
 pub fn parse_clk_comment_secs(comment: &str) -> Option<f32> {
     static RE: Lazy<Regex> = Lazy::new(|| {
-        Regex::new(r"\[%clk\s*([0-9]+):([0-9]{1,2})(?::([0-9]{1,2}))?\]").unwrap()
+        Regex::new(r"\[%clk\s*([0-9]+):([0-9]{1,2}(?:\.[0-9]+)?)(?::([0-9]{1,2}(?:\.[0-9]+)?))?\]")
+            .unwrap()
     });
 
     let caps = RE.captures(comment)?;
-    let a: u32 = caps.get(1)?.as_str().parse().ok()?;
-    let b: u32 = caps.get(2)?.as_str().parse().ok()?;
-    let c_opt = caps.get(3).and_then(|m| m.as_str().parse::<u32>().ok());
-    if b > 59 {
-        return None;
+    parse_clock_fields(
+        caps.get(1)?.as_str(),
+        caps.get(2)?.as_str(),
+        caps.get(3).map(|m| m.as_str()),
+    )
+}
+
+pub fn parse_emt_comment_secs(comment: &str) -> Option<f32> {
+    static RE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"\[%emt\s*([0-9]+):([0-9]{1,2}(?:\.[0-9]+)?)(?::([0-9]{1,2}(?:\.[0-9]+)?))?\]")
+            .unwrap()
+    });
+
+    let caps = RE.captures(comment)?;
+    parse_clock_fields(
+        caps.get(1)?.as_str(),
+        caps.get(2)?.as_str(),
+        caps.get(3).map(|m| m.as_str()),
+    )
+}
+
+pub fn parse_eval_comment(comment: &str) -> Option<EmbeddedEval> {
+    static RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\[%eval\s*(#)?([+-]?[0-9]+(?:\.[0-9]+)?)").unwrap());
+
+    let caps = RE.captures(comment)?;
+    let value = caps.get(2)?.as_str();
+    if caps.get(1).is_some() {
+        return value.parse::<i32>().ok().map(EmbeddedEval::Mate);
     }
-    if let Some(c) = c_opt {
-        if c > 59 {
-            return None;
+    let pawns: f64 = value.parse().ok()?;
+    Some(EmbeddedEval::Cp((pawns * 100.0).round() as i32))
+}
+
+/// Converts `H:MM:SS(.s)` or `M:SS(.s)` fields to seconds. Only the last field may carry a
+/// fractional part, so Chess.com tenths and Lichess bullet clocks keep their precision.
+fn parse_clock_fields(a: &str, b: &str, c: Option<&str>) -> Option<f32> {
+    let a: u32 = a.parse().ok()?;
+    let secs = match c {
+        Some(c) => {
+            let b: u32 = b.parse().ok()?;
+            let c: f64 = c.parse().ok()?;
+            if b > 59 || c >= 60.0 {
+                return None;
+            }
+            (a * 3600 + b * 60) as f64 + c
         }
-    }
+        None => {
+            let b: f64 = b.parse().ok()?;
+            if b >= 60.0 {
+                return None;
+            }
+            (a * 60) as f64 + b
+        }
+    };
 
-    let secs = if let Some(c) = c_opt {
-        (a * 3600 + b * 60 + c) as f32
-    } else {
-        (a * 60 + b) as f32
+    Some(secs as f32)
+}
+
+/// Per-colour starting clocks from `WhiteClock`/`BlackClock` headers, used by time-odds games.
+pub fn parse_starting_clocks(headers: &HashMap<String, String>) -> (Option<u32>, Option<u32>) {
+    let clock = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| parse_clock_value(v))
+            .map(|secs| secs.round() as u32)
     };
+    (clock("WhiteClock"), clock("BlackClock"))
+}
 
-    Some(secs)
+/// Parses `H:MM:SS`, `M:SS` or plain seconds.
+pub fn parse_clock_value(value: &str) -> Option<f32> {
+    let parts: Vec<&str> = value.trim().split(':').collect();
+    match parts.as_slice() {
+        [secs] => secs.parse::<f32>().ok().filter(|s| *s >= 0.0),
+        [a, b] => parse_clock_fields(a, b, None),
+        [a, b, c] => parse_clock_fields(a, b, Some(c)),
+        _ => None,
+    }
+}
+
+/// Armageddon games are marked by an `Armageddon` header or by the event name.
+pub fn detect_armageddon(headers: &HashMap<String, String>) -> bool {
+    if let Some(value) = headers.get("Armageddon") {
+        return matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "1" | "true" | "yes"
+        );
+    }
+    headers
+        .get("Event")
+        .map(|e| e.to_ascii_lowercase().contains("armageddon"))
+        .unwrap_or(false)
 }
 
 pub fn parse_time_control_header(headers: &HashMap<String, String>) -> Option<TimeControl> {
@@ -134,14 +325,83 @@ pub fn parse_time_control_header(headers: &HashMap<String, String>) -> Option<Ti
     parse_time_control_value(tc)
 }
 
+/// Parses PGN `TimeControl` values: `180+2`, sudden death `300`, move-count periods such as
+/// FIDE `40/5400+30:1800+30`, correspondence `1/259200`, and US-style delay controls like
+/// `G/90;d5` (a trailing `d5` is US delay, `b5` Bronstein).
 pub fn parse_time_control_value(tc: &str) -> Option<TimeControl> {
-    if tc == "-" {
+    static DELAY_RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^(?P<rest>.*?)\s*[;+]?\s*(?P<delay>[dDbB][0-9]+)$").unwrap());
+
+    let tc = tc.trim();
+    if tc == "-" || tc == "?" {
         return None;
     }
-    let mut parts = tc.split('+');
-    let base = parts.next()?.parse::<u32>().ok()?;
-    let inc = parts.next().and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+    let (tc, delay) = match DELAY_RE.captures(tc) {
+        Some(caps) => (
+            caps.name("rest")?.as_str(),
+            Some(parse_delay_value(caps.name("delay")?.as_str())?),
+        ),
+        None => (tc, None),
+    };
+
+    let mut stages = tc
+        .split(':')
+        .map(parse_time_control_stage)
+        .collect::<Option<Vec<_>>>()?
+        .into_iter();
+    let first = stages.next()?;
     Some(TimeControl {
+        base_secs: first.base_secs,
+        increment_secs: first.increment_secs,
+        moves: first.moves,
+        later_stages: stages.collect(),
+        delay,
+        black_base_secs: None,
+    })
+}
+
+/// Parses a delay spec: `d5` (US delay) or `b5` (Bronstein), in seconds.
+pub fn parse_delay_value(value: &str) -> Option<TimeDelay> {
+    let value = value.trim();
+    let mut chars = value.chars();
+    let kind = match chars.next()?.to_ascii_lowercase() {
+        'd' => DelayKind::Simple,
+        'b' => DelayKind::Bronstein,
+        _ => return None,
+    };
+    let secs = chars.as_str().parse::<u32>().ok()?;
+    Some(TimeDelay { kind, secs })
+}
+
+fn parse_time_control_stage(stage: &str) -> Option<TimeControlStage> {
+    let stage = stage.trim();
+    let upper = stage.to_ascii_uppercase();
+    if let Some(minutes) = upper
+        .strip_prefix("G/")
+        .or_else(|| upper.strip_prefix("SD/"))
+    {
+        return Some(TimeControlStage {
+            moves: None,
+            base_secs: minutes.trim().parse::<u32>().ok()? * 60,
+            increment_secs: 0,
+        });
+    }
+
+    let (moves, clock) = match stage.split_once('/') {
+        Some((moves, clock)) => (Some(moves.trim().parse::<u32>().ok()?), clock),
+        None => (None, stage),
+    };
+    let mut parts = clock.split('+');
+    let base = parts.next()?.trim().parse::<u32>().ok()?;
+    let inc = match parts.next() {
+        Some(v) => v.trim().parse::<u32>().ok()?,
+        None => 0,
+    };
+    if parts.next().is_some() || moves == Some(0) {
+        return None;
+    }
+    Some(TimeControlStage {
+        moves,
         base_secs: base,
         increment_secs: inc,
     })
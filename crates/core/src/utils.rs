@@ -7,6 +7,16 @@ pub fn mover_cp(cp_white: i32, mover: Color) -> i32 {
     }
 }
 
+/// 64-bit FNV-1a; stable across platforms and Rust versions, unlike `DefaultHasher`.
+pub fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
 pub fn sigmoid(x: f32) -> f32 {
     1.0 / (1.0 + (-x).exp())
 }
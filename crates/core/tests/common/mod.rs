@@ -0,0 +1,95 @@
+//! A scripted stand-in for a UCI engine, for tests that need real engine processes.
+
+#![allow(dead_code)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use timelens_core::AnalysisConfig;
+
+/// Score reported for a position: depends only on the `position` command.
+pub const POSITION_SCORE: &str = "$(( ${#pos} % 97 ))";
+/// Score that also depends on how many searches ran since `ucinewgame`, like an engine whose
+/// answer depends on its hash table.
+pub const HISTORY_SCORE: &str = "$(( ${#pos} % 97 + searches ))";
+
+/// A shell script speaking enough UCI for the pipeline. Every command it receives is appended to
+/// `commands`, and every process start to `starts`. `on_go` runs before each search reply, so it
+/// can crash or stall the engine (and keep state under `$dir`); `score` is a shell expression over `$pos` and `$searches`.
+pub struct FakeEngine {
+    pub dir: PathBuf,
+    pub path: PathBuf,
+}
+
+impl FakeEngine {
+    pub fn new(name: &str, on_go: &str, score: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("timelens_{}_{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).expect("engine dir");
+        let script = format!(
+            r#"#!/bin/sh
+dir="{dir}"
+echo start >> "$dir/starts"
+pos=""
+searches=0
+while read -r cmd; do
+  echo "$cmd" >> "$dir/commands"
+  case "$cmd" in
+    uci)
+      echo "id name FakeFish"
+      echo "option name Threads type spin default 1 min 1 max 512"
+      echo "option name Hash type spin default 16 min 1 max 33554432"
+      echo "uciok" ;;
+    isready) echo "readyok" ;;
+    ucinewgame) searches=0 ;;
+    position*) pos="$cmd" ;;
+    go*)
+      {on_go}
+      searches=$((searches + 1))
+      score={score}
+      echo "info depth 1 seldepth 1 multipv 1 score cp $score nodes 10 nps 100 pv e2e4"
+      echo "bestmove e2e4" ;;
+    quit) exit 0 ;;
+  esac
+done
+"#,
+            dir = dir.display(),
+        );
+        let path = dir.join("engine.sh");
+        std::fs::write(&path, script).expect("write engine");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).expect("chmod");
+        Self { dir, path }
+    }
+
+    /// Depth-1, single-line analysis with this engine.
+    pub fn config(&self) -> AnalysisConfig {
+        AnalysisConfig {
+            engine_path: self.path.display().to_string(),
+            depth: 1,
+            multipv: 1,
+            ..AnalysisConfig::default()
+        }
+    }
+
+    pub fn commands(&self) -> Vec<String> {
+        read_lines(&self.dir.join("commands"))
+    }
+
+    pub fn starts(&self) -> usize {
+        read_lines(&self.dir.join("starts")).len()
+    }
+}
+
+impl Drop for FakeEngine {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+fn read_lines(path: &Path) -> Vec<String> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
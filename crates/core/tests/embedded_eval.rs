@@ -0,0 +1,130 @@
+use timelens_core::analysis::pipeline::EvalSource;
+use timelens_core::model::{LabelKind, Score, MATE_METRIC_CP};
+use timelens_core::pgn::{parse_eval_comment, EmbeddedEval};
+use timelens_core::{analyze_pgn, AnalysisConfig};
+
+#[test]
+fn parse_eval_comment_handles_cp_and_mate() {
+    assert_eq!(
+        parse_eval_comment("[%eval 0.34] [%clk 0:03:00]"),
+        Some(EmbeddedEval::Cp(34))
+    );
+    assert_eq!(
+        parse_eval_comment("[%eval -1.5]"),
+        Some(EmbeddedEval::Cp(-150))
+    );
+    assert_eq!(
+        parse_eval_comment("[%eval #-3]"),
+        Some(EmbeddedEval::Mate(-3))
+    );
+    assert_eq!(parse_eval_comment("[%clk 0:03:00]"), None);
+}
+
+#[tokio::test]
+async fn embedded_mode_builds_summaries_without_engine() {
+    let pgn = r#"
+[Event "Rated Blitz game"]
+[Site "https://lichess.org/xxxx"]
+[Result "*"]
+[TimeControl "180+0"]
+
+1. e4 { [%eval 0.3] [%clk 0:03:00] } e5 { [%eval 0.25] [%clk 0:03:00] }
+2. Qh5 { [%eval -0.1] [%clk 0:02:58] } Nc6 { [%eval 0.0] [%clk 0:02:57] }
+3. Bc4 { [%eval 0.0] [%clk 0:02:55] } Nf6 { [%eval #3] [%clk 0:02:50] }
+*
+"#;
+
+    let cfg = AnalysisConfig {
+        eval_source: EvalSource::Embedded,
+        ..AnalysisConfig::default()
+    };
+    let analysis = analyze_pgn(pgn, cfg).await.expect("analysis");
+    assert_eq!(analysis.plies.len(), 6);
+
+    let first = &analysis.plies[0].engine_before;
+    assert_eq!(first.best_cp_white, None);
+    assert_eq!(first.played_cp_white, Some(30));
+
+    let queen_move = &analysis.plies[2].engine_before;
+    assert_eq!(queen_move.best_cp_white, Some(25));
+    assert_eq!(queen_move.punish_cp_mover, Some(35));
+
+    let blunder = &analysis.plies[5].engine_before;
+    assert_eq!(blunder.best_cp_white, Some(0));
+    assert_eq!(blunder.played_cp_white, Some(100_000));
+    assert_eq!(analysis.plies[5].metrics.cp_eval_after, 100_000);
+}
+
+#[tokio::test]
+async fn mate_swings_are_capped_and_missed_mates_labelled() {
+    let pgn = r#"
+[Event "Rated Blitz game"]
+[Site "https://lichess.org/xxxx"]
+[Result "*"]
+[TimeControl "180+0"]
+
+1. e4 { [%eval 0.3] [%clk 0:03:00] } e5 { [%eval 0.3] [%clk 0:03:00] }
+2. Bc4 { [%eval 0.3] [%clk 0:02:55] } Nc6 { [%eval 0.3] [%clk 0:02:55] }
+3. Qh5 { [%eval 0.3] [%clk 0:02:50] } Nf6 { [%eval #1] [%clk 0:02:50] }
+4. Qe2 { [%eval 0.2] [%clk 0:02:40] }
+*
+"#;
+
+    let cfg = AnalysisConfig {
+        eval_source: EvalSource::Embedded,
+        ..AnalysisConfig::default()
+    };
+    let analysis = analyze_pgn(pgn, cfg).await.expect("analysis");
+
+    let walked_into_mate = &analysis.plies[5];
+    assert_eq!(
+        walked_into_mate.engine_before.punish_cp_mover,
+        Some(MATE_METRIC_CP - 30)
+    );
+    assert_eq!(
+        walked_into_mate.engine_before.played_score_white,
+        Some(Score::Mate(1))
+    );
+
+    let missed = &analysis.plies[6];
+    assert_eq!(
+        missed.engine_before.punish_cp_mover,
+        Some(MATE_METRIC_CP - 20)
+    );
+    assert_eq!(missed.label.kind, LabelKind::MissedMate);
+    assert!(missed
+        .label
+        .explanation
+        .starts_with("Mate in 1 was available."));
+
+    let worst = analysis.summary.avg_punish_cp_mover.expect("average");
+    assert!(worst <= MATE_METRIC_CP as f32);
+}
+
+#[tokio::test]
+async fn config_hash_tracks_result_affecting_settings() {
+    let base = AnalysisConfig {
+        eval_source: EvalSource::Embedded,
+        ..AnalysisConfig::default()
+    };
+    let moved = AnalysisConfig {
+        engine_path: "/opt/stockfish".to_string(),
+        engine_pool_size: 4,
+        eval_cache_dir: Some(std::env::temp_dir()),
+        ..base.clone()
+    };
+    let deeper = AnalysisConfig {
+        depth: base.depth + 4,
+        ..base.clone()
+    };
+    assert_eq!(base.config_hash(), moved.config_hash());
+    assert_ne!(base.config_hash(), deeper.config_hash());
+
+    let pgn = "[Event \"Casual\"]\n\n1. e4 { [%eval 0.3] } e5 { [%eval 0.2] } *\n";
+    let first = analyze_pgn(pgn, base.clone()).await.expect("analysis");
+    let second = analyze_pgn(pgn, deeper).await.expect("analysis");
+    assert_eq!(first.meta.config_hash, base.config_hash());
+    assert!(first.meta.engine.is_none());
+    assert!(first.meta.comparable_with(&first.meta));
+    assert!(!first.meta.comparable_with(&second.meta));
+}
@@ -0,0 +1,171 @@
+use shakmaty::{variant::Variant, CastlingMode};
+use timelens_core::analysis::eval::normalize_summary_for_white;
+use timelens_core::analysis::labeling::quote_refutation;
+use timelens_core::analysis::pipeline::analyze_pgns;
+use timelens_core::analysis::position::pv_to_san;
+use timelens_core::analysis::time_equity::shift_prob_by_cp;
+use timelens_core::engine::parse::{parse_option_name, UciInfoAccumulator};
+use timelens_core::model::{format_san_line, Color, Label, LabelKind, SearchLimits, Wdl};
+use timelens_core::{analyze_pgn, AnalysisConfig};
+
+#[cfg(unix)]
+mod common;
+
+#[test]
+fn accumulator_keeps_full_pv_and_seldepth() {
+    let mut acc = UciInfoAccumulator::new(2);
+    acc.ingest_line("info depth 10 seldepth 14 multipv 1 score cp 35 pv e2e4 e7e5");
+    acc.ingest_line(
+        "info depth 12 seldepth 19 multipv 1 score cp 31 nodes 4000 nps 6000 pv e2e4 e7e5 g1f3",
+    );
+    acc.ingest_line("info depth 12 seldepth 17 multipv 2 score cp 20 pv d2d4 d7d5");
+    let summary = acc.into_summary();
+
+    assert_eq!(summary.lines.len(), 2);
+    let best = &summary.lines[0];
+    assert_eq!(best.uci, "e2e4");
+    assert_eq!(best.pv_uci, vec!["e2e4", "e7e5", "g1f3"]);
+    assert_eq!(best.seldepth, Some(19));
+    assert_eq!(summary.lines[1].pv_uci, vec!["d2d4", "d7d5"]);
+}
+
+#[test]
+fn pv_converts_to_numbered_san_and_quotes_refutations() {
+    let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR b KQkq - 3 3";
+    let pv: Vec<String> = ["c6d4", "f3f7", "e8f7"]
+        .iter()
+        .map(|m| m.to_string())
+        .collect();
+    let san = pv_to_san(fen, Variant::Chess, CastlingMode::Standard, &pv).expect("san");
+    assert_eq!(san, vec!["Nd4", "Qxf7#"]);
+    assert_eq!(format_san_line(6, &san), "3...Nd4 4.Qxf7#");
+    assert_eq!(format_san_line(7, &san), "4.Nd4 Qxf7#");
+
+    let mut label = Label {
+        kind: LabelKind::SnapBlunder,
+        severity: 0.9,
+        title: "Snap blunder".to_string(),
+        explanation: "Spent 0.4s".to_string(),
+        tips: Vec::new(),
+    };
+    quote_refutation(&mut label, 7, &["Qxf7#".to_string()]);
+    assert_eq!(label.explanation, "Spent 0.4s; refuted after 4.Qxf7#");
+
+    let mut neutral = Label {
+        kind: LabelKind::Neutral,
+        ..label.clone()
+    };
+    quote_refutation(&mut neutral, 7, &["Qxf7#".to_string()]);
+    assert_eq!(neutral.explanation, label.explanation);
+}
+
+#[test]
+fn wdl_is_parsed_normalised_and_shifted() {
+    let mut acc = UciInfoAccumulator::new(1);
+    acc.ingest_line("info depth 20 multipv 1 score cp -40 wdl 60 700 240 nodes 900 pv e7e5");
+    let mut summary = acc.into_summary();
+    assert_eq!(
+        summary.lines[0].wdl,
+        Some(Wdl {
+            win: 60,
+            draw: 700,
+            loss: 240
+        })
+    );
+
+    normalize_summary_for_white(&mut summary, Color::Black);
+    let wdl = summary.lines[0].wdl.expect("wdl");
+    assert_eq!((wdl.win, wdl.loss), (240, 60));
+    assert!((wdl.expected_score() - 0.59).abs() < 1e-6);
+    assert!((wdl.win_prob() - 0.24).abs() < 1e-6);
+
+    let p = wdl.expected_score();
+    assert_eq!(shift_prob_by_cp(1.2, p, 0), p);
+    assert!(shift_prob_by_cp(1.2, p, 50) > p);
+    assert!(shift_prob_by_cp(1.2, p, -50) < p);
+
+    assert_eq!(
+        parse_option_name("option name UCI_ShowWDL type check default false").as_deref(),
+        Some("UCI_ShowWDL")
+    );
+    assert_eq!(
+        parse_option_name("option name Skill Level type spin default 20 min 0 max 20").as_deref(),
+        Some("Skill Level")
+    );
+    assert_eq!(parse_option_name("id name Stockfish 16"), None);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn node_limited_search_is_single_threaded_from_an_empty_hash() {
+    let engine = common::FakeEngine::new("nodes", "", common::POSITION_SCORE);
+    let pgn = "[Event \"Nodes\"]\n[Result \"*\"]\n\n1. e4 e5 2. Nf3 *\n";
+    let cfg = AnalysisConfig {
+        nodes: Some(5_000),
+        movetime_ms: Some(200),
+        ..engine.config()
+    };
+
+    let analysis = analyze_pgn(pgn, cfg.clone()).await.expect("analysis");
+    assert_eq!(
+        analysis.meta.search_limits,
+        Some(SearchLimits {
+            depth: None,
+            movetime_ms: None,
+            nodes: Some(5_000),
+            multipv: 1,
+        })
+    );
+    let commands = engine.commands();
+    assert!(commands
+        .iter()
+        .any(|c| c == "setoption name Threads value 1"));
+    let searches: Vec<usize> = commands
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.starts_with("go"))
+        .map(|(i, _)| i)
+        .collect();
+    assert!(!searches.is_empty());
+    for i in searches {
+        assert!(commands[i].starts_with("go nodes 5000"), "{}", commands[i]);
+        // ucinewgame, isready, position, go: every search starts from a cleared hash.
+        assert_eq!(commands[i - 3], "ucinewgame");
+    }
+
+    let threaded = AnalysisConfig {
+        threads: Some(4),
+        ..cfg
+    };
+    let err = analyze_pgn(pgn, threaded)
+        .await
+        .expect_err("threads rejected");
+    assert!(format!("{:#}", err).contains("single engine thread"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn per_game_rule_options_do_not_change_the_recorded_engine_setup() {
+    let engine = common::FakeEngine::new("rule_options", "", common::POSITION_SCORE);
+    let standard = "[Event \"Standard\"]\n[Result \"*\"]\n\n1. e4 *\n\n";
+    let chess960 = "[Event \"960\"]\n[Variant \"Chess960\"]\n[SetUp \"1\"]\n\
+        [FEN \"bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w HFhf - 0 1\"]\n[Result \"*\"]\n\n\
+        1. e4 *\n\n";
+    let pgn = format!("{}{}{}", standard, chess960, standard);
+
+    let outcomes = analyze_pgns(&pgn, engine.config()).await.expect("batch");
+    let metas: Vec<_> = outcomes
+        .iter()
+        .map(|outcome| &outcome.analysis().expect("analysed").meta)
+        .collect();
+
+    let commands = engine.commands();
+    assert!(commands.contains(&"setoption name UCI_Chess960 value true".to_string()));
+    assert!(commands.contains(&"setoption name UCI_Chess960 value false".to_string()));
+    for meta in &metas {
+        let options = &meta.engine.as_ref().expect("engine").options;
+        assert!(!options.contains_key("UCI_Chess960"), "{:?}", options);
+    }
+    assert!(metas[0].comparable_with(metas[1]));
+    assert!(metas[0].comparable_with(metas[2]));
+}
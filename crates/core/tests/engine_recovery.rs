@@ -0,0 +1,176 @@
+#![cfg(unix)]
+
+mod common;
+
+use timelens_core::analysis::pipeline::{analyze_game_stream, analyze_pgns, EvalSource};
+use timelens_core::engine::EngineTimeouts;
+use timelens_core::model::GameOutcome;
+use timelens_core::pgn::read_games;
+use timelens_core::{analyze_pgn, AnalysisConfig};
+
+use common::{FakeEngine, POSITION_SCORE};
+
+const GAME: &str = r#"
+[Event "Recovery"]
+[Result "*"]
+
+1. e4 *
+"#;
+
+fn config(engine: &FakeEngine) -> AnalysisConfig {
+    AnalysisConfig {
+        engine_timeouts: EngineTimeouts {
+            handshake_ms: 5_000,
+            read_ms: 1_000,
+        },
+        ..engine.config()
+    }
+}
+
+#[tokio::test]
+async fn crashed_engine_is_restarted_and_the_position_retried() {
+    let on_go = r#"if [ ! -e "$dir/crashed" ]; then touch "$dir/crashed"; echo "NNUE network not found" >&2; exit 3; fi"#;
+    let engine = FakeEngine::new("engine_crash_once", on_go, POSITION_SCORE);
+
+    let analysis = analyze_pgn(GAME, config(&engine)).await.expect("analysis");
+
+    assert_eq!(analysis.meta.engine_restarts, 1);
+    assert_eq!(analysis.plies.len(), 1);
+    assert_eq!(analysis.plies[0].engine_before.lines[0].uci, "e2e4");
+}
+
+#[tokio::test]
+async fn exhausted_restarts_report_engine_stderr() {
+    let engine = FakeEngine::new(
+        "engine_crash_always",
+        r#"echo "NNUE network not found" >&2; exit 3"#,
+        POSITION_SCORE,
+    );
+
+    let err = analyze_pgn(GAME, config(&engine))
+        .await
+        .expect_err("engine keeps crashing");
+
+    let message = format!("{:#}", err);
+    assert!(message.contains("engine exited"), "{}", message);
+    assert!(message.contains("NNUE network not found"), "{}", message);
+}
+
+#[tokio::test]
+async fn stalled_engine_times_out() {
+    let engine = FakeEngine::new("engine_stall", "sleep 5", POSITION_SCORE);
+    let cfg = AnalysisConfig {
+        engine_timeouts: EngineTimeouts {
+            handshake_ms: 5_000,
+            read_ms: 200,
+        },
+        engine_restarts: 0,
+        ..config(&engine)
+    };
+
+    let err = analyze_pgn(GAME, cfg).await.expect_err("engine stalls");
+
+    assert!(format!("{:#}", err).contains("did not respond within 200 ms"));
+}
+
+#[tokio::test]
+async fn failed_game_does_not_stop_the_batch() {
+    let pgn = r#"
+[Event "Good"]
+[Result "*"]
+
+1. e4 { [%eval 0.3] } e5 { [%eval 0.3] } *
+
+[Event "Broken"]
+[Result "*"]
+
+1. e4 { [%eval 0.3] } e4 { [%eval 0.3] } *
+
+[Event "Also good"]
+[Result "*"]
+
+1. d4 { [%eval 0.2] } d5 { [%eval 0.2] } *
+"#;
+    let cfg = AnalysisConfig {
+        eval_source: EvalSource::Embedded,
+        ..AnalysisConfig::default()
+    };
+    let outcomes = analyze_pgns(pgn, cfg).await.expect("batch");
+
+    assert_eq!(outcomes.len(), 3);
+    assert!(outcomes[0].analysis().is_some());
+    assert!(outcomes[2].analysis().is_some());
+    let GameOutcome::Failed(failure) = &outcomes[1] else {
+        panic!("second game should fail");
+    };
+    assert_eq!(failure.game_index, 1);
+    assert_eq!(
+        failure.headers.get("Event").map(String::as_str),
+        Some("Broken")
+    );
+
+    let json = serde_json::to_value(&outcomes[1]).expect("json");
+    assert!(json["error"].is_string());
+}
+
+const BATCH: &str = r#"
+[Event "First"]
+[Result "*"]
+
+1. e4 *
+
+[Event "Crashes the engine"]
+[Result "*"]
+
+1. h4 *
+
+[Event "Illegal"]
+[Result "*"]
+
+1. e4 e4 *
+
+[Event "Last"]
+[Result "*"]
+
+1. d4 *
+"#;
+
+/// Kills the engine whenever it is asked about a position with a pawn on h4.
+const CRASH_ON_H4: &str = r#"case "$pos" in *7P*) echo "segfault" >&2; exit 139 ;; esac"#;
+
+fn assert_batch_outcomes(outcomes: &[GameOutcome]) {
+    assert_eq!(outcomes.len(), 4);
+    assert!(outcomes[0].analysis().is_some());
+    assert!(outcomes[3].analysis().is_some());
+    let GameOutcome::Failed(crashed) = &outcomes[1] else {
+        panic!("second game should fail");
+    };
+    assert!(crashed.error.contains("segfault"), "{}", crashed.error);
+    assert!(outcomes[2].analysis().is_none());
+}
+
+#[tokio::test]
+async fn engine_crash_mid_batch_leaves_the_other_games_analysed() {
+    let engine = FakeEngine::new("engine_crash_batch", CRASH_ON_H4, POSITION_SCORE);
+    let outcomes = analyze_pgns(BATCH, config(&engine)).await.expect("batch");
+    assert_batch_outcomes(&outcomes);
+    // One start, one per restart, and a fresh engine after the crashed game. The illegal game
+    // fails before any search, so its engine is kept.
+    let restarts = config(&engine).engine_restarts as usize;
+    assert_eq!(engine.starts(), 1 + restarts + 1);
+
+    let pooled = FakeEngine::new("engine_crash_pool", CRASH_ON_H4, POSITION_SCORE);
+    let cfg = AnalysisConfig {
+        engine_pool_size: 2,
+        ..config(&pooled)
+    };
+    let mut outcomes = Vec::new();
+    let count = analyze_game_stream(read_games(BATCH.as_bytes()), cfg, |outcome| {
+        outcomes.push(outcome);
+        Ok(())
+    })
+    .await
+    .expect("stream");
+    assert_eq!(count, 4);
+    assert_batch_outcomes(&outcomes);
+}
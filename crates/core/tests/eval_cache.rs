@@ -0,0 +1,225 @@
+use shakmaty::{variant::Variant, CastlingMode};
+use timelens_core::analysis::cache::{EvalCache, SearchLimits};
+use timelens_core::analysis::position::position_key;
+use timelens_core::model::{EngineLine, EngineSummary};
+
+#[cfg(unix)]
+mod common;
+
+fn summary(depth: u16, lines: u8) -> EngineSummary {
+    EngineSummary {
+        depth,
+        nodes: 0,
+        nps: 0,
+        lines: (1..=lines)
+            .map(|multipv| EngineLine {
+                multipv,
+                uci: format!("move{}", multipv),
+                cp_white: 100 - multipv as i32 * 10,
+                mate: None,
+                wdl: None,
+                seldepth: None,
+                pv_uci: vec![format!("move{}", multipv)],
+                pv_san: Vec::new(),
+            })
+            .collect(),
+        played_cp_white: None,
+        best_cp_white: None,
+        played_score_white: None,
+        best_score_white: None,
+        punish_cp_mover: None,
+        spread_k_cp_mover: None,
+        gap_12_cp_mover: None,
+        complexity_cp_mover: None,
+    }
+}
+
+fn depth(depth: u16, multipv: u8) -> SearchLimits {
+    SearchLimits {
+        depth: Some(depth),
+        movetime_ms: None,
+        nodes: None,
+        multipv,
+    }
+}
+
+#[test]
+fn deeper_results_serve_shallower_requests_across_runs() {
+    let dir = std::env::temp_dir().join(format!("timelens_eval_cache_{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+
+    let mut cache = EvalCache::open(&dir, 2).expect("open");
+    let key = EvalCache::key("stockfish", "startpos");
+    cache.insert(key.clone(), depth(20, 4), summary(20, 4));
+    cache.insert(key.clone(), depth(12, 2), summary(12, 2));
+    cache.insert(
+        EvalCache::key("stockfish", "a"),
+        depth(10, 1),
+        summary(10, 1),
+    );
+    assert!(cache.get(&key, &depth(14, 2)).is_some());
+    cache.insert(
+        EvalCache::key("stockfish", "b"),
+        depth(10, 1),
+        summary(10, 1),
+    );
+    assert_eq!(cache.len(), 2);
+    cache.save().expect("save");
+
+    let mut reopened = EvalCache::open(&dir, 2).expect("reopen");
+    assert_eq!(reopened.len(), 2);
+    let hit = reopened
+        .get(&key, &depth(14, 2))
+        .expect("deeper entry reused");
+    assert_eq!(hit.depth, 20);
+    assert_eq!(hit.lines.len(), 2);
+    assert!(reopened.get(&key, &depth(24, 2)).is_none());
+    assert!(reopened.get(&key, &depth(14, 5)).is_none());
+    let movetime = SearchLimits {
+        depth: None,
+        movetime_ms: Some(100),
+        ..depth(14, 2)
+    };
+    assert!(reopened.get(&key, &movetime).is_none());
+    assert!(reopened
+        .get(&EvalCache::key("stockfish", "a"), &depth(10, 1))
+        .is_none());
+
+    // Node-limited searches are only reused for the exact same node count and MultiPV.
+    let nodes = |nodes: u64, multipv: u8| SearchLimits {
+        depth: None,
+        nodes: Some(nodes),
+        ..depth(1, multipv)
+    };
+    assert!(reopened.get(&key, &nodes(100_000, 2)).is_none());
+    reopened.insert(key.clone(), nodes(500_000, 2), summary(18, 2));
+    assert!(reopened.get(&key, &nodes(500_000, 2)).is_some());
+    assert!(reopened.get(&key, &nodes(250_000, 2)).is_none());
+    assert!(reopened.get(&key, &nodes(500_000, 1)).is_none());
+    assert!(reopened.get(&key, &depth(1, 1)).is_none());
+
+    reopened.clear();
+    reopened.save().expect("save");
+    assert!(EvalCache::open(&dir, 2).expect("reopen").is_empty());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn inserts_past_the_limit_evict_least_recently_used() {
+    let dir = std::env::temp_dir().join(format!("timelens_eval_evict_{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let mut cache = EvalCache::open(&dir, 20).expect("open");
+    std::fs::remove_dir_all(&dir).ok();
+
+    let key = |n: usize| EvalCache::key("stockfish", &n.to_string());
+    for n in 0..30 {
+        cache.insert(key(n), depth(10, 1), summary(10, 1));
+        // Keep the first position in use.
+        cache.get(&key(0), &depth(10, 1));
+        assert!(cache.len() <= 20);
+    }
+
+    assert!(cache.get(&key(0), &depth(10, 1)).is_some());
+    assert!(cache.get(&key(1), &depth(10, 1)).is_none());
+    assert!(cache.get(&key(29), &depth(10, 1)).is_some());
+}
+
+#[test]
+fn position_keys_ignore_counters_but_keep_castling_and_en_passant() {
+    let key = |fen: &str| position_key(fen, Variant::Chess, CastlingMode::Standard).expect("key");
+
+    let start = key("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+    assert_eq!(
+        start,
+        key("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 8 5")
+    );
+    assert_ne!(
+        start,
+        key("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Kkq - 0 1")
+    );
+    assert_ne!(
+        start,
+        key("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1")
+    );
+
+    // A double push only sets en passant when it can actually be taken.
+    let after_e4 = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq";
+    assert_eq!(
+        key(&format!("{} e3 0 1", after_e4)),
+        key(&format!("{} - 0 1", after_e4))
+    );
+    let capturable = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq";
+    assert_ne!(
+        key(&format!("{} e3 0 3", capturable)),
+        key(&format!("{} - 0 3", capturable))
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn thread_and_hash_settings_are_part_of_the_cache_key() {
+    use timelens_core::{analyze_pgn, AnalysisConfig};
+
+    let engine = common::FakeEngine::new("eval_cache_threads", "", common::POSITION_SCORE);
+    // Searches restricted to the played move are never cached.
+    let searches = || {
+        let commands = engine.commands();
+        commands
+            .iter()
+            .filter(|c| c.starts_with("go") && !c.contains("searchmoves"))
+            .count()
+    };
+    let pgn = "[Event \"Cache\"]\n[Result \"*\"]\n\n1. e4 e5 *\n";
+    let run = |threads: u32, hash_mb: u32| {
+        let cfg = AnalysisConfig {
+            eval_cache_dir: Some(engine.dir.join("cache")),
+            threads: Some(threads),
+            hash_mb: Some(hash_mb),
+            ..engine.config()
+        };
+        analyze_pgn(pgn, cfg)
+    };
+
+    run(1, 16).await.expect("first run");
+    let cold = searches();
+    assert!(cold > 0);
+    run(1, 16).await.expect("same settings");
+    assert_eq!(searches(), cold);
+    run(2, 16).await.expect("more threads");
+    assert_eq!(searches(), 2 * cold);
+    run(2, 64).await.expect("bigger hash");
+    assert_eq!(searches(), 3 * cold);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sequential_batches_search_a_shared_opening_once() {
+    use timelens_core::analysis::pipeline::analyze_pgns;
+    use timelens_core::AnalysisConfig;
+
+    let engine = common::FakeEngine::new("eval_cache_batch", "", common::POSITION_SCORE);
+    let searches = || {
+        let commands = engine.commands();
+        commands
+            .iter()
+            .filter(|c| c.starts_with("go") && !c.contains("searchmoves"))
+            .count()
+    };
+    // Both games reach the start position and the positions after 1. e4 and 1... e5.
+    let pgn = "[Event \"A\"]\n[Result \"*\"]\n\n1. e4 e5 2. Nf3 *\n\n\
+               [Event \"B\"]\n[Result \"*\"]\n\n1. e4 e5 2. Bc4 *\n";
+
+    let outcomes = analyze_pgns(pgn, engine.config())
+        .await
+        .expect("sequential");
+    assert!(outcomes.iter().all(|o| o.analysis().is_some()));
+    assert_eq!(searches(), 5);
+
+    // Pooled depth searches keep to their own game, so the opening is searched by each.
+    let pooled = AnalysisConfig {
+        engine_pool_size: 2,
+        ..engine.config()
+    };
+    analyze_pgns(pgn, pooled).await.expect("pooled");
+    assert_eq!(searches(), 5 + 8);
+}
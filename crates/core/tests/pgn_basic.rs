@@ -1,5 +1,15 @@
-use timelens_core::analysis::position::build_ply_records_with_fens;
-use timelens_core::pgn::parse_single_game;
+use shakmaty::variant::Variant;
+use std::collections::HashMap;
+use timelens_core::analysis::pipeline::EvalSource;
+use timelens_core::analysis::position::{
+    build_ply_records_and_warnings, build_ply_records_with_fens,
+};
+use timelens_core::clocks::derive_clock_before_and_think_times;
+use timelens_core::model::SourcePlatform;
+use timelens_core::pgn::{
+    detect_chess960, detect_variant, parse_single_game, parse_time_control_header,
+};
+use timelens_core::{analyze_pgn, AnalysisConfig};
 
 #[test]
 fn parse_and_fen_basic() {
@@ -52,3 +62,214 @@ fn parse_clk_allows_single_digit_fields() {
     assert_eq!(game.plies[0].clock_after_secs, Some(180.0));
     assert_eq!(game.plies[1].clock_after_secs, Some(125.0));
 }
+
+#[test]
+fn parse_clk_keeps_fractional_seconds() {
+    let pgn = r#"
+[Event "Test"]
+[Site "https://www.chess.com/game/live/1"]
+[Result "*"]
+[TimeControl "60"]
+
+1. e4 { [%clk 0:00:04.7] } e5 { [%clk 0:59.9] }
+2. Nf3 { [%clk 0:00:02.3] } Nc6 { [%clk 0:00:60.0] }
+*
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    assert_eq!(game.plies.len(), 4);
+    assert!((game.plies[0].clock_after_secs.unwrap() - 4.7).abs() < 1e-4);
+    assert!((game.plies[1].clock_after_secs.unwrap() - 59.9).abs() < 1e-4);
+    assert!((game.plies[2].clock_after_secs.unwrap() - 2.3).abs() < 1e-4);
+    assert_eq!(game.plies[3].clock_after_secs, None);
+
+    let mut plies = build_ply_records_with_fens(&game).expect("fen");
+    derive_clock_before_and_think_times(&mut plies, None, SourcePlatform::ChessCom);
+    assert!((plies[2].think_time_secs.unwrap() - 2.4).abs() < 1e-4);
+}
+
+#[test]
+fn emt_only_rebuilds_clocks_from_time_control() {
+    let pgn = r#"
+[Event "Broadcast"]
+[Site "Berlin"]
+[Result "*"]
+[TimeControl "5400+30"]
+
+1. d4 { [%emt 0:00:05] } Nf6 { [%emt 0:01:23] }
+2. c4 { [%emt 0:00:40] } e6 { [%emt 0:00:10] }
+*
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    assert_eq!(game.plies[1].elapsed_move_secs, Some(83.0));
+    assert_eq!(game.plies[1].clock_after_secs, None);
+
+    let mut plies = build_ply_records_with_fens(&game).expect("fen");
+    let tc = parse_time_control_header(&game.headers);
+    derive_clock_before_and_think_times(&mut plies, tc, SourcePlatform::Unknown);
+    assert_eq!(plies[1].think_time_secs, Some(83.0));
+    assert_eq!(plies[1].clock_after_secs, Some(5400.0 + 30.0 - 83.0));
+    assert_eq!(plies[2].clock_before_secs, Some(5425.0));
+    assert_eq!(plies[2].clock_after_secs, Some(5425.0 + 30.0 - 40.0));
+}
+
+#[test]
+fn variations_are_kept_with_fens() {
+    let pgn = r#"
+[Event "Lesson"]
+[Result "*"]
+
+1. e4 e5 (1... c5 { Sicilian } 2. Nf3 (2. c3) d6) 2. Nf3 { [%clk 0:02:58] } *
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    assert_eq!(game.plies.len(), 3);
+    assert_eq!(game.plies[1].variations.len(), 1);
+    assert_eq!(game.plies[2].clock_after_secs, Some(178.0));
+
+    let plies = build_ply_records_with_fens(&game).expect("fen");
+    let sideline = &plies[1].variations[0];
+    assert_eq!(sideline.len(), 3);
+    assert_eq!(sideline[0].san, "c5");
+    assert_eq!(sideline[0].ply_index, 2);
+    assert_eq!(sideline[0].fen_before, plies[1].fen_before);
+    assert_eq!(sideline[0].comment.as_deref(), Some(" Sicilian "));
+    assert_eq!(sideline[1].variations[0][0].san, "c3");
+    assert_eq!(sideline[2].uci, "d7d6");
+}
+
+#[tokio::test]
+async fn illegal_sideline_moves_cut_the_sideline_not_the_game() {
+    let pgn = r#"
+[Event "Lesson"]
+[Result "*"]
+
+1. e4 { [%eval 0.3] } e5 { [%eval 0.3] } (1... c5 2. Nf3 Ke7 3. d4) (1... Bc5) 2. Nf3 { [%eval 0.3] } *
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    let (plies, warnings) = build_ply_records_and_warnings(&game).expect("mainline intact");
+    assert_eq!(plies.len(), 3);
+    assert_eq!(plies[1].variations.len(), 1);
+    let sideline = &plies[1].variations[0];
+    assert_eq!(sideline.len(), 2);
+    assert_eq!(sideline[1].san, "Nf3");
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings[0].contains("Ke7"), "{}", warnings[0]);
+    assert!(warnings[1].contains("Bc5"), "{}", warnings[1]);
+
+    let cfg = AnalysisConfig {
+        eval_source: EvalSource::Embedded,
+        ..AnalysisConfig::default()
+    };
+    let analysis = analyze_pgn(pgn, cfg).await.expect("analysis");
+    assert_eq!(analysis.plies.len(), 3);
+    assert_eq!(analysis.meta.warnings, warnings);
+}
+
+#[test]
+fn setup_fen_header_sets_start_position_and_ply_index() {
+    let pgn = r#"
+[Event "From position"]
+[Result "*"]
+[SetUp "1"]
+[FEN "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"]
+
+3. Bb5 a6 4. Ba4 *
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    let plies = build_ply_records_with_fens(&game).expect("fen");
+    assert_eq!(plies.len(), 3);
+    assert_eq!(plies[0].ply_index, 5);
+    assert_eq!(plies[1].ply_index, 6);
+    assert_eq!(plies[0].uci, "f1b5");
+    assert!(plies[2].fen_after.ends_with("b KQkq - 1 4"));
+}
+
+#[test]
+fn chess960_castling_uses_king_takes_rook_uci() {
+    let pgn = r#"
+[Event "Rated Chess960 game"]
+[Site "https://lichess.org/xxxx"]
+[Variant "Chess960"]
+[SetUp "1"]
+[FEN "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w KQkq - 0 1"]
+[Result "*"]
+
+1. g4 g5 2. Ng3 Ng6 3. O-O O-O *
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    let plies = build_ply_records_with_fens(&game).expect("fen");
+    assert_eq!(plies.len(), 6);
+    assert_eq!(plies[4].uci, "f1g1");
+    assert_eq!(plies[5].uci, "f8g8");
+}
+
+#[test]
+fn chess960_header_spellings_are_plain_chess() {
+    for spelling in [
+        "Chess960",
+        "chess 960",
+        "Chess 960",
+        "Fischerandom",
+        "Fischer Random",
+        "fischerandom chess",
+    ] {
+        let headers = HashMap::from([("Variant".to_string(), spelling.to_string())]);
+        assert!(detect_chess960(&headers), "{}", spelling);
+        assert_eq!(
+            detect_variant(&headers),
+            Some(Variant::Chess),
+            "{}",
+            spelling
+        );
+    }
+
+    let pgn = r#"
+[Event "Casual game"]
+[Variant "Fischerandom"]
+[SetUp "1"]
+[FEN "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w KQkq - 0 1"]
+[Result "*"]
+
+1. g4 g5 2. Ng3 Ng6 3. O-O O-O *
+"#;
+    let game = parse_single_game(pgn).expect("parse");
+    let plies = build_ply_records_with_fens(&game).expect("fen");
+    assert_eq!(plies[4].uci, "f1g1");
+}
+
+#[test]
+fn crazyhouse_drops_build_variant_positions() {
+    let pgn = r#"
+[Event "Rated Crazyhouse game"]
+[Site "https://lichess.org/xxxx"]
+[Variant "Crazyhouse"]
+[Result "*"]
+
+1. e4 d5 2. exd5 Qxd5 3. Nc3 Qa5 4. P@d4 *
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    let plies = build_ply_records_with_fens(&game).expect("fen");
+    assert_eq!(plies.len(), 7);
+    assert_eq!(plies[6].uci, "P@d4");
+    assert!(plies[6].fen_before.contains("[Pp]"));
+}
+
+#[test]
+fn unsupported_variant_is_rejected() {
+    let pgn = r#"
+[Event "Bughouse"]
+[Variant "Bughouse"]
+[Result "*"]
+
+1. e4 *
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    assert!(build_ply_records_with_fens(&game).is_err());
+}
@@ -0,0 +1,66 @@
+use timelens_core::analysis::profile::{load_profile, parse_profiles};
+use timelens_core::AnalysisConfig;
+
+const PROFILES: &str = r#"{
+  "profiles": {
+    "ci": {
+      "path": "/usr/bin/stockfish",
+      "options": { "SyzygyPath": "/tb", "UCI_Elo": 1800, "UCI_LimitStrength": true },
+      "nodes": 200000,
+      "multipv": 2,
+      "threads": 1
+    },
+    "deep": { "depth": 24 }
+  }
+}"#;
+
+#[test]
+fn profiles_overlay_path_options_and_limits() {
+    let profiles = parse_profiles(PROFILES).expect("profiles");
+    let mut cfg = AnalysisConfig {
+        movetime_ms: Some(500),
+        ..AnalysisConfig::default()
+    };
+    cfg.engine_options
+        .insert("Contempt".to_string(), "10".to_string());
+    profiles["ci"].apply(&mut cfg);
+
+    assert_eq!(cfg.engine_path, "/usr/bin/stockfish");
+    assert_eq!(cfg.nodes, Some(200_000));
+    assert_eq!(cfg.movetime_ms, None);
+    assert_eq!(cfg.multipv, 2);
+    assert_eq!(cfg.threads, Some(1));
+    let options: Vec<(&str, &str)> = cfg
+        .engine_options
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    assert_eq!(
+        options,
+        vec![
+            ("Contempt", "10"),
+            ("SyzygyPath", "/tb"),
+            ("UCI_Elo", "1800"),
+            ("UCI_LimitStrength", "true")
+        ]
+    );
+
+    let before = cfg.clone();
+    profiles["deep"].apply(&mut cfg);
+    assert_eq!(cfg.depth, 24);
+    assert_eq!(cfg.nodes, before.nodes);
+    assert_eq!(cfg.engine_path, before.engine_path);
+}
+
+#[test]
+fn unknown_profiles_and_fields_are_rejected() {
+    assert!(parse_profiles(r#"{ "profiles": { "x": { "dept": 20 } } }"#).is_err());
+    assert!(parse_profiles(r#"{ "profiles": { "x": { "options": { "A": [1] } } } }"#).is_err());
+
+    let path = std::env::temp_dir().join(format!("timelens_profiles_{}.json", std::process::id()));
+    std::fs::write(&path, PROFILES).expect("write");
+    let err = load_profile(&path, "fast").expect_err("missing profile");
+    assert!(err.to_string().contains("available: ci, deep"));
+    assert!(load_profile(&path, "deep").is_ok());
+    std::fs::remove_file(&path).ok();
+}
@@ -0,0 +1,136 @@
+use std::io::Write;
+
+use timelens_core::analysis::pipeline::{analyze_game_stream, EvalSource};
+use timelens_core::pgn::{open_pgn_file, read_games};
+use timelens_core::AnalysisConfig;
+
+#[cfg(unix)]
+mod common;
+#[cfg(unix)]
+use common::{FakeEngine, HISTORY_SCORE, POSITION_SCORE};
+
+const TWO_GAMES: &str = r#"
+[Event "One"]
+[Result "*"]
+
+1. e4 { [%eval 0.3] } e5 { [%eval 0.3] } *
+
+[Event "Two"]
+[Result "*"]
+
+1. d4 { [%eval 0.2] } d5 { [%eval 0.2] } 2. c4 { [%eval 0.1] } *
+"#;
+
+#[test]
+fn gz_archive_streams_games() {
+    let path = std::env::temp_dir().join(format!("timelens_stream_{}.pgn.gz", std::process::id()));
+    let file = std::fs::File::create(&path).expect("create");
+    let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    encoder.write_all(TWO_GAMES.as_bytes()).expect("write");
+    encoder.finish().expect("finish");
+
+    let games: Vec<_> = open_pgn_file(&path)
+        .expect("open")
+        .collect::<anyhow::Result<_>>()
+        .expect("parse");
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(games.len(), 2);
+    assert_eq!(
+        games[1].headers.get("Event").map(String::as_str),
+        Some("Two")
+    );
+    assert_eq!(games[1].plies.len(), 3);
+}
+
+#[tokio::test]
+async fn stream_emits_each_game_in_order() {
+    let cfg = AnalysisConfig {
+        eval_source: EvalSource::Embedded,
+        ..AnalysisConfig::default()
+    };
+    let mut events = Vec::new();
+    let count = analyze_game_stream(read_games(TWO_GAMES.as_bytes()), cfg, |outcome| {
+        let analysis = outcome.analysis().expect("analysed");
+        events.push(analysis.meta.event.clone());
+        Ok(())
+    })
+    .await
+    .expect("stream");
+
+    assert_eq!(count, 2);
+    assert_eq!(
+        events,
+        vec![Some("One".to_string()), Some("Two".to_string())]
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn pooled_stream_keeps_input_order() {
+    // Searches take longer on longer positions, so pooled games finish out of order.
+    let engine = FakeEngine::new(
+        "pooled_stream",
+        "sleep 0.0$(( ${#pos} % 7 ))",
+        POSITION_SCORE,
+    );
+    let pgn = TWO_GAMES.repeat(3);
+    let run = |pool_size: usize| {
+        let cfg = AnalysisConfig {
+            engine_pool_size: pool_size,
+            ..engine.config()
+        };
+        let pgn = pgn.clone();
+        async move {
+            let mut events = Vec::new();
+            let count = analyze_game_stream(read_games(pgn.as_bytes()), cfg, |outcome| {
+                events.push(serde_json::to_value(&outcome).expect("json"));
+                Ok(())
+            })
+            .await
+            .expect("stream");
+            (count, events)
+        }
+    };
+
+    let (_, sequential) = run(1).await;
+    let (count, pooled) = run(4).await;
+
+    assert_eq!(count, 6);
+    assert_eq!(pooled, sequential);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn pooled_node_limited_batches_match_a_sequential_run() {
+    // The engine's score depends on how many searches ran since `ucinewgame`. Node-limited runs
+    // send one before every search, so it does not matter which game searched a shared position.
+    let engine = FakeEngine::new("pooled_batch", "sleep 0.0$(( ${#pos} % 7 ))", HISTORY_SCORE);
+    let pgn = TWO_GAMES.repeat(3);
+    let run = |pool_size: usize, cache: Option<&str>| {
+        let cfg = AnalysisConfig {
+            engine_pool_size: pool_size,
+            eval_cache_dir: cache.map(|name| engine.dir.join(name)),
+            nodes: Some(1_000),
+            ..engine.config()
+        };
+        let pgn = pgn.clone();
+        async move {
+            let mut events = Vec::new();
+            analyze_game_stream(read_games(pgn.as_bytes()), cfg, |outcome| {
+                // Header maps serialize in hash order, so outputs are compared as JSON values.
+                events.push(serde_json::to_value(&outcome).expect("json"));
+                Ok(())
+            })
+            .await
+            .expect("stream");
+            events
+        }
+    };
+
+    assert_eq!(run(4, None).await, run(1, None).await);
+    assert_eq!(
+        run(4, Some("pooled_cache")).await,
+        run(1, Some("sequential_cache")).await
+    );
+}
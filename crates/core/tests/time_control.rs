@@ -0,0 +1,413 @@
+use timelens_core::analysis::labeling::{label_move, LabelConfig};
+use timelens_core::analysis::pipeline::EvalSource;
+use timelens_core::analysis::position::build_ply_records_with_fens;
+use timelens_core::clocks::{derive_clock_before_and_think_times, interpolate_missing_clocks};
+use timelens_core::model::{
+    ClockAnomalyKind, Color, DelayKind, LabelKind, SourcePlatform, TimeControl, TimeControlSource,
+    TimeControlStage, TimeDelay,
+};
+use timelens_core::pgn::{parse_single_game, parse_time_control_value};
+use timelens_core::{analyze_pgn, AnalysisConfig};
+
+#[test]
+fn parses_simple_and_staged_time_controls() {
+    assert_eq!(
+        parse_time_control_value("180+2"),
+        Some(TimeControl::sudden_death(180, 2))
+    );
+    assert_eq!(
+        parse_time_control_value("300"),
+        Some(TimeControl::sudden_death(300, 0))
+    );
+    assert_eq!(parse_time_control_value("-"), None);
+    assert_eq!(parse_time_control_value("?"), None);
+
+    let fide = parse_time_control_value("40/5400+30:1800+30").expect("fide");
+    assert_eq!(fide.moves, Some(40));
+    assert_eq!(fide.base_secs, 5400);
+    assert_eq!(
+        fide.later_stages,
+        vec![TimeControlStage {
+            moves: None,
+            base_secs: 1800,
+            increment_secs: 30
+        }]
+    );
+    assert_eq!(fide.bonus_after_move(39), 0);
+    assert_eq!(fide.bonus_after_move(40), 1800);
+    assert_eq!(fide.bonus_after_move(41), 0);
+
+    let corr = parse_time_control_value("1/259200").expect("correspondence");
+    assert_eq!(corr.per_move_clock_secs(17), Some(259200));
+    assert_eq!(corr.bonus_after_move(17), 0);
+
+    let repeating = parse_time_control_value("40/7200").expect("repeating");
+    assert_eq!(repeating.bonus_after_move(40), 7200);
+    assert_eq!(repeating.bonus_after_move(80), 7200);
+}
+
+#[test]
+fn stage_bonus_is_credited_at_move_forty() {
+    let tc = TimeControl {
+        base_secs: 120,
+        increment_secs: 0,
+        moves: Some(2),
+        later_stages: vec![TimeControlStage {
+            moves: None,
+            base_secs: 60,
+            increment_secs: 0,
+        }],
+        delay: None,
+        black_base_secs: None,
+    };
+    let pgn = r#"
+[Event "Stage test"]
+[Result "*"]
+
+1. e4 { [%clk 0:01:50] } e5 { [%clk 0:01:55] }
+2. Nf3 { [%clk 0:02:40] } Nc6 { [%clk 0:02:50] }
+3. Bb5 { [%clk 0:02:30] } *
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    let mut plies = build_ply_records_with_fens(&game).expect("fen");
+    derive_clock_before_and_think_times(&mut plies, Some(tc), SourcePlatform::Unknown);
+
+    assert_eq!(plies[2].think_time_secs, Some(10.0));
+    assert_eq!(plies[3].think_time_secs, Some(5.0));
+    assert_eq!(plies[4].clock_before_secs, Some(160.0));
+    assert_eq!(plies[4].think_time_secs, Some(10.0));
+}
+
+#[test]
+fn delay_window_is_added_back_to_think_time() {
+    let tc = parse_time_control_value("G/90;d5").expect("delay control");
+    assert_eq!(tc.base_secs, 5400);
+    assert_eq!(
+        tc.delay,
+        Some(TimeDelay {
+            kind: DelayKind::Simple,
+            secs: 5
+        })
+    );
+    assert_eq!(
+        parse_time_control_value("5400+b10")
+            .and_then(|t| t.delay)
+            .map(|d| d.kind),
+        Some(DelayKind::Bronstein)
+    );
+
+    let pgn = r#"
+[Event "Delay test"]
+[TimeControl "G/90;d5"]
+[Result "*"]
+
+1. e4 { [%clk 1:30:00] } e5 { [%clk 1:29:50] }
+2. Nf3 { [%clk 1:29:30] } *
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    let mut plies = build_ply_records_with_fens(&game).expect("fen");
+    derive_clock_before_and_think_times(&mut plies, Some(tc), SourcePlatform::Unknown);
+
+    assert_eq!(plies[0].think_time_secs, Some(0.0));
+    assert_eq!(plies[1].think_time_secs, Some(15.0));
+    assert_eq!(plies[2].think_time_secs, Some(35.0));
+}
+
+#[tokio::test]
+async fn armageddon_uses_per_colour_clocks_and_draw_odds() {
+    let pgn = r#"
+[Event "Norway Chess Armageddon"]
+[TimeControl "600+0"]
+[WhiteClock "0:10:00"]
+[BlackClock "0:07:00"]
+[Result "*"]
+
+1. e4 { [%eval 0.0] [%clk 0:09:50] } e5 { [%eval 0.0] [%clk 0:06:55] }
+2. Nf3 { [%eval 0.0] [%clk 0:09:40] } *
+"#;
+
+    let cfg = AnalysisConfig {
+        eval_source: EvalSource::Embedded,
+        ..AnalysisConfig::default()
+    };
+    let analysis = analyze_pgn(pgn, cfg).await.expect("analysis");
+    assert!(analysis.meta.armageddon);
+    let tc = analysis.meta.time_control.as_ref().expect("time control");
+    assert_eq!(tc.base_secs_for(Color::White), 600);
+    assert_eq!(tc.base_secs_for(Color::Black), 420);
+
+    assert_eq!(analysis.plies[1].ply.think_time_secs, Some(5.0));
+    let metrics = &analysis.plies[1].metrics;
+    assert!(metrics.tau_white_cp > 0);
+    assert!(metrics.p_eval_after < 0.5);
+}
+
+#[test]
+fn clock_anomalies_are_reported() {
+    let pgn = r#"
+[Event "Anomaly test"]
+[TimeControl "180+0"]
+[Result "*"]
+
+1. e4 { [%clk 0:02:55] } e5 { [%clk 0:02:58] }
+2. Nf3 { [%clk 0:02:59] } Nc6
+3. Bb5 { [%clk 0:02:50] } *
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    let mut plies = build_ply_records_with_fens(&game).expect("fen");
+    let tc = parse_time_control_value("180+0");
+    let diagnostics = derive_clock_before_and_think_times(&mut plies, tc, SourcePlatform::Unknown);
+
+    let kinds: Vec<(u32, ClockAnomalyKind)> = diagnostics
+        .anomalies
+        .iter()
+        .map(|a| (a.ply_index, a.kind))
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (3, ClockAnomalyKind::UnexpectedGain),
+            (4, ClockAnomalyKind::Missing),
+        ]
+    );
+    assert_eq!(diagnostics.anomalies[0].magnitude_secs, 4.0);
+    assert_eq!(plies[2].think_time_secs, Some(0.0));
+    assert!(diagnostics.is_suspect(3));
+    assert!(!diagnostics.is_suspect(4));
+    assert_eq!(diagnostics.confidence, 3.0 / 5.0);
+}
+
+#[test]
+fn missing_clocks_are_interpolated() {
+    let pgn = r#"
+[Event "Gap test"]
+[TimeControl "300+2"]
+[Result "*"]
+
+1. e4 { [%clk 0:04:52] } e5 { [%clk 0:05:00] }
+2. Nf3 Nc6 { [%clk 0:04:55] }
+3. Bb5 a6 { [%clk 0:04:50] }
+4. Ba4 { [%clk 0:04:40] } *
+"#;
+
+    let game = parse_single_game(pgn).expect("parse");
+    let mut plies = build_ply_records_with_fens(&game).expect("fen");
+    let tc = parse_time_control_value("300+2");
+    derive_clock_before_and_think_times(&mut plies, tc.clone(), SourcePlatform::Unknown);
+    assert_eq!(plies[2].think_time_secs, None);
+    assert_eq!(plies[4].think_time_secs, None);
+
+    interpolate_missing_clocks(&mut plies, tc.as_ref());
+    assert!(!plies[0].think_time_estimated);
+    for idx in [2, 4, 6] {
+        assert_eq!(plies[idx].think_time_secs, Some(6.0));
+        assert!(plies[idx].think_time_estimated);
+    }
+    assert_eq!(plies[4].clock_before_secs, Some(288.0));
+    assert_eq!(plies[4].clock_after_secs, Some(284.0));
+    assert!(!plies[3].think_time_estimated);
+}
+
+#[tokio::test]
+async fn missing_time_control_is_inferred_from_clocks() {
+    let pgn = r#"
+[Event "No TimeControl"]
+[TimeControl "-"]
+[Result "*"]
+
+1. e4 { [%eval 0.2] [%clk 0:03:00] } e5 { [%eval 0.2] [%clk 0:03:00] }
+2. Nf3 { [%eval 0.2] [%clk 0:02:58] } Nc6 { [%eval 0.2] [%clk 0:03:01] }
+3. Bb5 { [%eval 0.2] [%clk 0:02:59] } a6 { [%eval 0.2] [%clk 0:02:55] }
+4. Ba4 { [%eval 0.2] [%clk 0:02:50] } Nf6 { [%eval 0.2] [%clk 0:02:56] }
+5. O-O { [%eval 0.2] [%clk 0:02:51] } Be7 { [%eval 0.2] [%clk 0:02:51] }
+*
+"#;
+
+    let cfg = AnalysisConfig {
+        eval_source: EvalSource::Embedded,
+        ..AnalysisConfig::default()
+    };
+    let analysis = analyze_pgn(pgn, cfg).await.expect("analysis");
+    assert_eq!(
+        analysis.meta.time_control,
+        Some(TimeControl::sudden_death(180, 1))
+    );
+    assert_eq!(
+        analysis.meta.time_control_source,
+        Some(TimeControlSource::Inferred)
+    );
+    let confidence = analysis.meta.time_control_confidence.expect("confidence");
+    assert!(confidence > 0.9, "confidence {}", confidence);
+    assert_eq!(analysis.plies[2].ply.think_time_secs, Some(3.0));
+}
+
+#[tokio::test]
+async fn low_confidence_inference_leaves_the_time_control_unset() {
+    let pgn = r#"
+[Event "Few clocks"]
+[Result "*"]
+
+1. e4 { [%eval 0.2] [%clk 0:03:00] } e5 { [%eval 0.2] [%clk 0:03:00] }
+2. Nf3 { [%eval 0.2] [%clk 0:02:58] } Nc6 { [%eval 0.2] } *
+"#;
+
+    let cfg = AnalysisConfig {
+        eval_source: EvalSource::Embedded,
+        ..AnalysisConfig::default()
+    };
+    let analysis = analyze_pgn(pgn, cfg).await.expect("analysis");
+    assert_eq!(analysis.meta.time_control, None);
+    assert_eq!(analysis.meta.time_control_source, None);
+    assert_eq!(analysis.meta.time_control_confidence, None);
+    let warnings = &analysis.clock_diagnostics.warnings;
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("was not used"), "{}", warnings[0]);
+}
+
+#[tokio::test]
+async fn delay_without_time_control_is_reported() {
+    let pgn = r#"
+[Event "No clocks"]
+[Result "*"]
+
+1. e4 { [%eval 0.2] } e5 { [%eval 0.2] } *
+"#;
+
+    let delay = TimeDelay {
+        kind: DelayKind::Simple,
+        secs: 5,
+    };
+    let cfg = AnalysisConfig {
+        eval_source: EvalSource::Embedded,
+        delay: Some(delay),
+        ..AnalysisConfig::default()
+    };
+    let analysis = analyze_pgn(pgn, cfg.clone()).await.expect("analysis");
+    assert_eq!(analysis.meta.time_control, None);
+    assert_eq!(analysis.clock_diagnostics.warnings.len(), 1);
+
+    // Starting clocks create a time control, which then carries the delay.
+    let cfg = AnalysisConfig {
+        white_clock_secs: Some(300),
+        ..cfg
+    };
+    let analysis = analyze_pgn(pgn, cfg).await.expect("analysis");
+    let tc = analysis.meta.time_control.expect("time control");
+    assert_eq!(tc.delay, Some(delay));
+    assert!(analysis.clock_diagnostics.warnings.is_empty());
+}
+
+#[test]
+fn platform_clock_models_flag_premoves_and_absorb_lag() {
+    let chesscom = r#"
+[Event "Live Chess"]
+[TimeControl "180"]
+[Result "*"]
+
+1. e4 { [%clk 0:02:59.9] } e5 { [%clk 0:02:58.2] }
+2. Nf3 { [%clk 0:02:55.1] } Nc6 { [%clk 0:02:58.1] } *
+"#;
+    let game = parse_single_game(chesscom).expect("parse");
+    let mut plies = build_ply_records_with_fens(&game).expect("fen");
+    derive_clock_before_and_think_times(
+        &mut plies,
+        parse_time_control_value("180"),
+        SourcePlatform::ChessCom,
+    );
+    let premoves: Vec<bool> = plies.iter().map(|p| p.premove).collect();
+    assert_eq!(premoves, vec![false, false, false, true]);
+
+    let lichess = r#"
+[Event "Rated Blitz game"]
+[TimeControl "180+0"]
+[Result "*"]
+
+1. e4 { [%clk 0:03:00] } e5 { [%clk 0:03:00] }
+2. Nf3 { [%clk 0:02:55] } Nc6 { [%clk 0:02:56] }
+3. Bb5 { [%clk 0:02:56] } *
+"#;
+    let game = parse_single_game(lichess).expect("parse");
+    let mut plies = build_ply_records_with_fens(&game).expect("fen");
+    let diagnostics = derive_clock_before_and_think_times(
+        &mut plies,
+        parse_time_control_value("180+0"),
+        SourcePlatform::Lichess,
+    );
+    assert!(diagnostics.anomalies.is_empty());
+    assert_eq!(plies[4].think_time_secs, Some(0.0));
+    // A lag refund on whole-second readings is not evidence of a premove.
+    assert!(plies.iter().all(|p| !p.premove));
+
+    // Whole seconds: the lone 0s capture may just be fast, the instant recapture is a premove.
+    let lichess_exchange = r#"
+[Event "Rated Blitz game"]
+[Site "https://lichess.org/abcdefgh"]
+[TimeControl "180+0"]
+[Result "*"]
+
+1. e4 { [%clk 0:03:00] } e5 { [%clk 0:03:00] }
+2. Nf3 { [%clk 0:02:55] } Nc6 { [%clk 0:02:54] }
+3. Bb5 { [%clk 0:02:50] } a6 { [%clk 0:02:50] }
+4. Bxc6 { [%clk 0:02:50] } dxc6 { [%clk 0:02:50] }
+5. O-O { [%clk 0:02:45] } *
+"#;
+    let game = parse_single_game(lichess_exchange).expect("parse");
+    let mut plies = build_ply_records_with_fens(&game).expect("fen");
+    derive_clock_before_and_think_times(
+        &mut plies,
+        parse_time_control_value("180+0"),
+        SourcePlatform::Lichess,
+    );
+    let premoves: Vec<(bool, bool)> = plies
+        .iter()
+        .map(|p| (p.premove, p.premove_estimated))
+        .collect();
+    let mut expected = vec![(false, false); 9];
+    expected[7] = (true, true);
+    assert_eq!(premoves, expected);
+
+    let lichess_subsecond = r#"
+[Event "Rated Blitz game"]
+[TimeControl "180+0"]
+[Result "*"]
+
+1. e4 { [%clk 0:03:00] } e5 { [%clk 0:03:00] }
+2. Nf3 { [%clk 0:02:55.4] } Nc6 { [%clk 0:02:56.2] }
+3. Bb5 { [%clk 0:02:55.4] } a6 { [%clk 0:02:56.5] } *
+"#;
+    let game = parse_single_game(lichess_subsecond).expect("parse");
+    let mut plies = build_ply_records_with_fens(&game).expect("fen");
+    derive_clock_before_and_think_times(
+        &mut plies,
+        parse_time_control_value("180+0"),
+        SourcePlatform::Lichess,
+    );
+    let premoves: Vec<bool> = plies.iter().map(|p| p.premove).collect();
+    assert_eq!(premoves, vec![false, false, false, false, true, false]);
+    assert!(plies.iter().all(|p| !p.premove_estimated));
+
+    let cfg = LabelConfig::default();
+    let premove = label_move(
+        &cfg,
+        Some(0.0),
+        Some(176.0),
+        true,
+        Some(400),
+        Some(400),
+        -0.3,
+    );
+    assert_eq!(premove.kind, LabelKind::PremoveBlunder);
+    let snap = label_move(
+        &cfg,
+        Some(0.0),
+        Some(176.0),
+        false,
+        Some(400),
+        Some(400),
+        -0.3,
+    );
+    assert_eq!(snap.kind, LabelKind::SnapBlunder);
+}